@@ -1,3 +1,5 @@
+use crate::Error;
+
 /// BUFFER SIZE should be lower than u32 max -1 and 2...
 pub struct RingBuffer<const BUFFER_SIZE: usize> {
     rp_: usize, // read pointer
@@ -13,9 +15,9 @@ impl<const BUFFER_SIZE: usize> RingBuffer<BUFFER_SIZE> {
             buffer_: [0; BUFFER_SIZE],
         }
     }
-    pub fn enqueue(&mut self, data: u8) -> Result<(), ()> {
+    pub fn enqueue(&mut self, data: u8) -> Result<(), Error> {
         if ((self.wp_ - self.rp_) & (BUFFER_SIZE - 1)) == (BUFFER_SIZE - 1) {
-            return Err(());
+            return Err(Error::BufferFull);
         }
         self.buffer_[self.wp_] = data;
         self.inc_wp();
@@ -47,6 +49,7 @@ impl<const BUFFER_SIZE: usize> RingBuffer<BUFFER_SIZE> {
 #[cfg(test)]
 mod tests {
     use crate::buffer::{self, RingBuffer};
+    use crate::Error;
 
     #[test]
     fn enqueue_and_dequeue() {
@@ -57,6 +60,16 @@ mod tests {
         assert_eq!(r.dequeue(), Some(1));
     }
 
+    #[test]
+    fn buffer_full() {
+        let mut r = RingBuffer::<4>::new();
+        assert_eq!(r.enqueue(1), Ok(()));
+        assert_eq!(r.enqueue(2), Ok(()));
+        assert_eq!(r.enqueue(3), Ok(()));
+        assert_eq!(r.enqueue(4), Err(Error::BufferFull));
+        assert_eq!(r.dequeue(), Some(1));
+    }
+
     #[test]
     fn enqueue() {
         let mut r = RingBuffer::<128>::new();
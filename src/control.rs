@@ -0,0 +1,363 @@
+//! Cascaded position/velocity/current controller of the X series.
+//!
+//! Gains and goals are read from the control table every tick, so the host can tune the
+//! controller with ordinary write instructions. The gains are scaled as described in the e-Manual,
+//! e.g. `KPP = PositionPGain / 128`, and the output is PWM in the unit of `GoalPWM`.
+//!
+//! The position and velocity loops follow `PositionTrajectory` and `VelocityTrajectory`, so run
+//! `ProfileGenerator::update` before `Controller::update` every tick. The hardware is accessed
+//! only through `Actuator`.
+use crate::control_data::OperatingMode;
+use crate::control_table::{BitsW, ControlTableAccess, R};
+use crate::layout::ControlTableLayout;
+
+use core::time::Duration;
+
+/// Motor driver and sensors.
+pub trait Actuator {
+    /// `pwm` is in the range of -885..=885 and positive to increase the encoder count.
+    fn set_pwm(&mut self, pwm: i16);
+    /// Multi-turn position of the motor in ticks.
+    fn encoder(&mut self) -> i32;
+    /// Motor current in the unit of `PresentCurrent`, positive to increase the encoder count.
+    fn current(&mut self) -> i16;
+}
+
+/// Gains of the current loop. They depend on the motor and are not in the control table.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CurrentGain {
+    pub p: f32,
+    pub i: f32,
+}
+
+// PIの積分項。出力が飽和している間は飽和を強める方向に積分しない
+#[derive(Default)]
+struct Integrator {
+    sum: f32,
+}
+
+impl Integrator {
+    fn pi(&mut self, error: f32, kp: f32, ki: f32, limit: f32) -> f32 {
+        let output = kp * error + ki * (self.sum + error);
+        if (output < limit || error < 0.0) && (output > -limit || error > 0.0) {
+            self.sum += error;
+        }
+        // 積分項だけで上限を超えないようにする
+        if ki > 0.0 {
+            self.sum = self.sum.clamp(-limit / ki, limit / ki);
+        }
+        (kp * error + ki * self.sum).clamp(-limit, limit)
+    }
+    fn reset(&mut self) {
+        self.sum = 0.0;
+    }
+}
+
+pub struct Controller {
+    period: Duration,
+    current_gain: CurrentGain,
+    last_encoder: Option<i32>,
+    last_position_error: f32,
+    last_velocity_trajectory: f32,
+    position: Integrator,
+    velocity: Integrator,
+    current: Integrator,
+}
+
+impl Controller {
+    /// `period` is the interval between calls of `update`.
+    pub fn new(period: Duration, current_gain: CurrentGain) -> Self {
+        Self {
+            period,
+            current_gain,
+            last_encoder: None,
+            last_position_error: 0.0,
+            last_velocity_trajectory: 0.0,
+            position: Integrator::default(),
+            velocity: Integrator::default(),
+            current: Integrator::default(),
+        }
+    }
+
+    /// Clear the integral terms, e.g. after the operating mode is switched.
+    pub fn reset(&mut self) {
+        self.last_position_error = 0.0;
+        self.last_velocity_trajectory = 0.0;
+        self.position.reset();
+        self.velocity.reset();
+        self.current.reset();
+    }
+
+    /// Run one control tick: update the present values and drive the actuator in `mode`.
+    pub fn update<D, A>(&mut self, ctd: &D, actuator: &mut A, mode: OperatingMode)
+    where
+        D: ControlTableAccess,
+        A: Actuator,
+    {
+        let encoder = actuator.encoder();
+        let current = actuator.current();
+        let ticks = encoder.wrapping_sub(self.last_encoder.unwrap_or(encoder));
+        self.last_encoder = Some(encoder);
+
+        let mut snapshot = ctd.snapshot();
+        let r = snapshot.read();
+        // 逆回転モードではモータとレジスタの向きが逆になる
+        let sign = if r.is_reverse() { -1 } else { 1 };
+        let position = r.raw_to_position(encoder);
+        let velocity = sign as f32 * self.to_velocity_unit::<D::Layout>(ticks);
+        let current = sign as i16 * current;
+
+        let pwm = if r.torque_enable() == 0 {
+            self.reset();
+            0.0
+        } else {
+            self.output(&r, mode, position as f32, velocity, current as f32)
+        };
+        let pwm = pwm as i16;
+        actuator.set_pwm(sign as i16 * pwm);
+
+        snapshot
+            .write()
+            .present_position()
+            .bits(position)
+            .present_velocity()
+            .bits(velocity as i32)
+            .present_current()
+            .bits(current)
+            .present_pwm()
+            .bits(pwm);
+        ctd.commit(&snapshot);
+    }
+
+    // 位置の変化量をGoal Velocityの単位に変換する
+    fn to_velocity_unit<L: ControlTableLayout>(&self, ticks: i32) -> f32 {
+        let rpm = ticks as f32 / L::POSITION_RESOLUTION as f32 * 60.0 / self.period.as_secs_f32();
+        rpm / L::VELOCITY_UNIT
+    }
+
+    fn output<L: ControlTableLayout>(
+        &mut self,
+        r: &R<L>,
+        mode: OperatingMode,
+        position: f32,
+        velocity: f32,
+        current: f32,
+    ) -> f32 {
+        // Goal PWMはすべてのモードで出力の上限になる
+        let pwm_limit = r.goal_pwm().unsigned_abs().min(r.pwm_limit()) as f32;
+        let current_limit = r.goal_current().unsigned_abs().min(r.current_limit()) as f32;
+        match mode {
+            OperatingMode::PWMControMode => (r.goal_pwm() as f32).clamp(-pwm_limit, pwm_limit),
+            OperatingMode::CurrentControlMode => {
+                let goal = (r.goal_current() as f32).clamp(-current_limit, current_limit);
+                self.current_loop(goal - current, pwm_limit)
+            }
+            OperatingMode::VelocityControlMode => {
+                let limit = r.velocity_limit() as f32;
+                let goal = (r.velocity_trajectory() as f32).clamp(-limit, limit);
+                self.velocity.pi(
+                    goal - velocity,
+                    r.velocity_pgain() as f32 / 128.0,
+                    r.velocity_igain() as f32 / 65536.0,
+                    pwm_limit,
+                )
+            }
+            OperatingMode::PositionControlMode
+            | OperatingMode::ExtendedPosionControlMode
+            | OperatingMode::CurrentBasedPositionControlMode => {
+                let mut goal = r.position_trajectory() as f32;
+                if mode == OperatingMode::PositionControlMode {
+                    goal = goal.clamp(r.min_position_limit() as f32, r.max_position_limit() as f32);
+                }
+                let error = goal - position;
+                let d = error - self.last_position_error;
+                self.last_position_error = error;
+                // 軌道の速度と加速度によるフィードフォワード
+                let velocity_trajectory = r.velocity_trajectory() as f32;
+                let acceleration = velocity_trajectory - self.last_velocity_trajectory;
+                self.last_velocity_trajectory = velocity_trajectory;
+                let feedforward = r.feedforward1st_gain() as f32 / 4.0 * velocity_trajectory
+                    + r.feedforward2nd_gain() as f32 / 4.0 * acceleration;
+                let kd = r.position_dgain() as f32 / 16.0;
+                if mode == OperatingMode::CurrentBasedPositionControlMode {
+                    // 位置ループの出力を電流指令にする
+                    let goal_current = self.position.pi(
+                        error,
+                        r.position_pgain() as f32 / 128.0,
+                        r.position_igain() as f32 / 65536.0,
+                        current_limit,
+                    ) + kd * d
+                        + feedforward;
+                    let goal_current = goal_current.clamp(-current_limit, current_limit);
+                    self.current_loop(goal_current - current, pwm_limit)
+                } else {
+                    let pwm = self.position.pi(
+                        error,
+                        r.position_pgain() as f32 / 128.0,
+                        r.position_igain() as f32 / 65536.0,
+                        pwm_limit,
+                    ) + kd * d
+                        + feedforward;
+                    pwm.clamp(-pwm_limit, pwm_limit)
+                }
+            }
+        }
+    }
+
+    fn current_loop(&mut self, error: f32, pwm_limit: f32) -> f32 {
+        let gain = self.current_gain;
+        self.current.pi(error, gain.p, gain.i, pwm_limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::control::{Actuator, Controller, CurrentGain};
+    use crate::control_data::OperatingMode;
+    use crate::control_table::BitsW;
+    use crate::profile::ProfileGenerator;
+    use crate::{ControlTableData, XC330};
+    use core::time::Duration;
+
+    // PWMに比例した速度で回るモータ
+    struct Motor {
+        position: f32,
+        pwm: i16,
+    }
+
+    impl Actuator for Motor {
+        fn set_pwm(&mut self, pwm: i16) {
+            self.pwm = pwm;
+            self.position += pwm as f32 * 0.01;
+        }
+        fn encoder(&mut self) -> i32 {
+            self.position as i32
+        }
+        fn current(&mut self) -> i16 {
+            self.pwm
+        }
+    }
+
+    fn controller() -> Controller {
+        Controller::new(Duration::from_millis(1), CurrentGain { p: 0.5, i: 0.01 })
+    }
+
+    #[test]
+    fn torque_off() {
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        let mut motor = Motor {
+            position: 100.0,
+            pwm: 10,
+        };
+        let mut controller = controller();
+        ctd.modify(|_, w| w.goal_position().bits(2000).homing_offset().bits(10));
+        controller.update(&ctd, &mut motor, OperatingMode::PositionControlMode);
+        assert_eq!(motor.pwm, 0);
+        assert_eq!(ctd.read().present_position(), 110);
+        assert_eq!(ctd.read().present_current(), 10);
+    }
+
+    #[test]
+    fn position() {
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        ctd.modify(|_, w| {
+            w.goal_pwm()
+                .bits(885)
+                .goal_position()
+                .bits(2000)
+                .torque_enable()
+                .bits(1)
+        });
+        let mut motor = Motor {
+            position: 0.0,
+            pwm: 0,
+        };
+        let mut controller = controller();
+        let mut profile = ProfileGenerator::new(Duration::from_millis(1));
+        for _ in 0..1000 {
+            profile.update(&ctd, OperatingMode::PositionControlMode);
+            controller.update(&ctd, &mut motor, OperatingMode::PositionControlMode);
+        }
+        assert!((ctd.read().present_position() - 2000).abs() <= 2);
+
+        // 上限を超える目標は制限される
+        ctd.modify(|_, w| w.goal_position().bits(5000));
+        for _ in 0..1000 {
+            profile.update(&ctd, OperatingMode::PositionControlMode);
+            controller.update(&ctd, &mut motor, OperatingMode::PositionControlMode);
+        }
+        assert!((ctd.read().present_position() - 4095).abs() <= 2);
+
+        // 逆回転モードではモータは逆に回る
+        ctd.modify(|_, w| w.drive_mode().bits(0x01).goal_position().bits(-1000));
+        motor.position = 0.0;
+        for _ in 0..1000 {
+            profile.update(&ctd, OperatingMode::ExtendedPosionControlMode);
+            controller.update(&ctd, &mut motor, OperatingMode::ExtendedPosionControlMode);
+        }
+        assert!((motor.position - 1000.0).abs() <= 3.0);
+        assert!((ctd.read().present_position() + 1000).abs() <= 3);
+    }
+
+    #[test]
+    fn pwm_limit() {
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        ctd.modify(|_, w| {
+            w.goal_pwm()
+                .bits(100)
+                .goal_velocity()
+                .bits(400)
+                .torque_enable()
+                .bits(1)
+        });
+        let mut motor = Motor {
+            position: 0.0,
+            pwm: 0,
+        };
+        let mut controller = controller();
+        let mut profile = ProfileGenerator::new(Duration::from_millis(1));
+        for _ in 0..10 {
+            profile.update(&ctd, OperatingMode::VelocityControlMode);
+            controller.update(&ctd, &mut motor, OperatingMode::VelocityControlMode);
+        }
+        assert_eq!(motor.pwm, 100);
+        assert_eq!(ctd.read().present_pwm(), 100);
+
+        ctd.modify(|_, w| w.goal_pwm().bits(-50));
+        controller.update(&ctd, &mut motor, OperatingMode::PWMControMode);
+        assert_eq!(motor.pwm, -50);
+    }
+
+    #[test]
+    fn anti_windup() {
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        ctd.modify(|_, w| {
+            w.goal_pwm()
+                .bits(100)
+                .goal_current()
+                .bits(1000)
+                .torque_enable()
+                .bits(1)
+        });
+        // 電流が流れないモータで飽和させ続ける
+        struct Stalled;
+        impl Actuator for Stalled {
+            fn set_pwm(&mut self, _pwm: i16) {}
+            fn encoder(&mut self) -> i32 {
+                0
+            }
+            fn current(&mut self) -> i16 {
+                0
+            }
+        }
+        let mut controller = controller();
+        for _ in 0..10000 {
+            controller.update(&ctd, &mut Stalled, OperatingMode::CurrentControlMode);
+        }
+        assert_eq!(ctd.read().present_pwm(), 100);
+        // 目標が下がればすぐに出力も下がる
+        ctd.modify(|_, w| w.goal_current().bits(0));
+        controller.update(&ctd, &mut Stalled, OperatingMode::CurrentControlMode);
+        assert!(ctd.read().present_pwm() <= 100 / 2);
+    }
+}
@@ -1,5 +1,7 @@
-#[allow(dead_code)]
+use crate::control_table::ControlTable;
 
+/// Control mode selected by the `OperatingMode` register.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum OperatingMode {
     CurrentControlMode,
     VelocityControlMode,
@@ -9,8 +11,18 @@ pub enum OperatingMode {
     PWMControMode,
 }
 
-#[allow(dead_code)]
 impl OperatingMode {
+    pub fn from_value(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(OperatingMode::CurrentControlMode),
+            1 => Some(OperatingMode::VelocityControlMode),
+            3 => Some(OperatingMode::PositionControlMode),
+            4 => Some(OperatingMode::ExtendedPosionControlMode),
+            5 => Some(OperatingMode::CurrentBasedPositionControlMode),
+            16 => Some(OperatingMode::PWMControMode),
+            _ => None,
+        }
+    }
     pub fn to_value(&self) -> u8 {
         match self {
             OperatingMode::CurrentControlMode => 0,
@@ -21,4 +33,105 @@ impl OperatingMode {
             OperatingMode::PWMControMode => 16,
         }
     }
+    /// Goal register that the controller follows in this mode.
+    pub fn goal_register(&self) -> ControlTable {
+        match self {
+            OperatingMode::CurrentControlMode => ControlTable::GoalCurrent,
+            OperatingMode::VelocityControlMode => ControlTable::GoalVelocity,
+            OperatingMode::PositionControlMode
+            | OperatingMode::ExtendedPosionControlMode
+            | OperatingMode::CurrentBasedPositionControlMode => ControlTable::GoalPosition,
+            OperatingMode::PWMControMode => ControlTable::GoalPWM,
+        }
+    }
+}
+
+/// Baud rate selected by the `BaudRate` register.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BaudRate {
+    Bps9600,
+    Bps57600,
+    Bps115200,
+    Bps1M,
+    Bps2M,
+    Bps3M,
+    Bps4M,
+    Bps4_5M,
+}
+
+impl BaudRate {
+    pub fn from_value(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(BaudRate::Bps9600),
+            1 => Some(BaudRate::Bps57600),
+            2 => Some(BaudRate::Bps115200),
+            3 => Some(BaudRate::Bps1M),
+            4 => Some(BaudRate::Bps2M),
+            5 => Some(BaudRate::Bps3M),
+            6 => Some(BaudRate::Bps4M),
+            7 => Some(BaudRate::Bps4_5M),
+            _ => None,
+        }
+    }
+    pub fn to_value(&self) -> u8 {
+        match self {
+            BaudRate::Bps9600 => 0,
+            BaudRate::Bps57600 => 1,
+            BaudRate::Bps115200 => 2,
+            BaudRate::Bps1M => 3,
+            BaudRate::Bps2M => 4,
+            BaudRate::Bps3M => 5,
+            BaudRate::Bps4M => 6,
+            BaudRate::Bps4_5M => 7,
+        }
+    }
+    /// Bit rate in bps.
+    pub fn to_bps(&self) -> u32 {
+        match self {
+            BaudRate::Bps9600 => 9_600,
+            BaudRate::Bps57600 => 57_600,
+            BaudRate::Bps115200 => 115_200,
+            BaudRate::Bps1M => 1_000_000,
+            BaudRate::Bps2M => 2_000_000,
+            BaudRate::Bps3M => 3_000_000,
+            BaudRate::Bps4M => 4_000_000,
+            BaudRate::Bps4_5M => 4_500_000,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::control_data::{BaudRate, OperatingMode};
+    use crate::control_table::ControlTable;
+
+    #[test]
+    fn baud_rate() {
+        assert_eq!(BaudRate::from_value(3), Some(BaudRate::Bps1M));
+        assert_eq!(BaudRate::from_value(8), None);
+        assert_eq!(BaudRate::Bps4_5M.to_bps(), 4_500_000);
+        for v in 0..8 {
+            assert_eq!(BaudRate::from_value(v).unwrap().to_value(), v);
+        }
+    }
+
+    #[test]
+    fn operating_mode() {
+        assert_eq!(
+            OperatingMode::from_value(4),
+            Some(OperatingMode::ExtendedPosionControlMode)
+        );
+        assert_eq!(OperatingMode::from_value(2), None);
+        for v in [0, 1, 3, 4, 5, 16] {
+            assert_eq!(OperatingMode::from_value(v).unwrap().to_value(), v);
+        }
+        assert_eq!(
+            OperatingMode::CurrentBasedPositionControlMode.goal_register(),
+            ControlTable::GoalPosition
+        );
+        assert_eq!(
+            OperatingMode::PWMControMode.goal_register(),
+            ControlTable::GoalPWM
+        );
+    }
 }
@@ -1,629 +1,681 @@
 use crate::data_spec::{self, DataSpec};
+use crate::layout::{Access, ControlTableLayout, DataType, RegisterSpec, XC330};
 use core::cell::Cell;
 use core::{marker, mem};
-
-/// XC330相当のデータ量を持つControlTableを定義する
-#[allow(dead_code)]
-pub enum ControlTable {
-    ModelNumber,
-    ModelInformation,
-    FirmwareVersion,
-    ID,
-    BaudRate,
-    ReturnDelayTime,
-    DriveMode,
-    OperatingMode,
-    SecondaryID,
-    ProtocolType,
-    HomingOffset,
-    MovingThreshold,
-    TemperatureLimit,
-    MaxVoltageLimit,
-    MinVoltageLimit,
-    PWMLimit,
-    CurrentLimit,
-    VelocityLimit,
-    MaxPositionLimit,
-    MinPositionLimit,
-    StartupConfiguration,
-    PWMSlope,
-    Shutdown,
-    TorqueEnable,
-    LED,
-    StatusReturnLevel,
-    RegisteredInstruction,
-    HardwareErrorStatus,
-    VelocityIGain,
-    VelocityPgain,
-    PositionDGain,
-    PositionIGain,
-    PositionPGain,
-    Feedforward2ndGain,
-    Feedforward1stGain,
-    BusWatchdog,
-    GoalPWM,
-    GoalCurrent,
-    GoalVelocity,
-    ProfileAccleration,
-    ProfileVelocity,
-    GoalPosition,
-    RealtimeTick,
-    Moving,
-    MovingStatus,
-    PresentPWM,
-    PresentCurrent,
-    PresentVelocity,
-    PresentPosition,
-    VelocityTrajectory,
-    PositionTrajectory,
-    PresentInputVoltage,
-    PresentTemperature,
-    BackupReady,
-    IndirectAddress1,
-    IndirectAddress2,
-    IndirectAddress3,
-    IndirectAddress4,
-    IndirectAddress5,
-    IndirectAddress6,
-    IndirectAddress7,
-    IndirectAddress8,
-    IndirectAddress9,
-    IndirectAddress10,
-    IndirectAddress11,
-    IndirectAddress12,
-    IndirectAddress13,
-    IndirectAddress14,
-    IndirectAddress15,
-    IndirectAddress16,
-    IndirectAddress17,
-    IndirectAddress18,
-    IndirectAddress19,
-    IndirectAddress20,
-    IndirectData1,
-    IndirectData2,
-    IndirectData3,
-    IndirectData4,
-    IndirectData5,
-    IndirectData6,
-    IndirectData7,
-    IndirectData8,
-    IndirectData9,
-    IndirectData10,
-    IndirectData11,
-    IndirectData12,
-    IndirectData13,
-    IndirectData14,
-    IndirectData15,
-    IndirectData16,
-    IndirectData17,
-    IndirectData18,
-    IndirectData19,
-    IndirectData20,
-}
+use critical_section::Mutex;
 
 pub trait CustomInt<const N: usize> {
     type Ty;
 }
-impl CustomInt<{ ControlTable::ModelNumber as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::ModelInformation as usize }> for () {
-    type Ty = u32;
-}
-impl CustomInt<{ ControlTable::FirmwareVersion as usize }> for () {
-    type Ty = u8;
-}
-impl CustomInt<{ ControlTable::ID as usize }> for () {
-    type Ty = u8;
-}
-impl CustomInt<{ ControlTable::BaudRate as usize }> for () {
-    type Ty = u8;
-}
-impl CustomInt<{ ControlTable::ReturnDelayTime as usize }> for () {
-    type Ty = u8;
-}
-impl CustomInt<{ ControlTable::DriveMode as usize }> for () {
-    type Ty = u8;
-}
-impl CustomInt<{ ControlTable::OperatingMode as usize }> for () {
-    type Ty = u8;
-}
-impl CustomInt<{ ControlTable::SecondaryID as usize }> for () {
-    type Ty = u8;
-}
-impl CustomInt<{ ControlTable::ProtocolType as usize }> for () {
-    type Ty = u8;
-}
-impl CustomInt<{ ControlTable::HomingOffset as usize }> for () {
-    type Ty = i32;
-}
-impl CustomInt<{ ControlTable::MovingThreshold as usize }> for () {
-    type Ty = u32;
-}
-impl CustomInt<{ ControlTable::TemperatureLimit as usize }> for () {
-    type Ty = u8;
-}
-impl CustomInt<{ ControlTable::MaxVoltageLimit as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::MinVoltageLimit as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::PWMLimit as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::CurrentLimit as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::VelocityLimit as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::MaxPositionLimit as usize }> for () {
-    type Ty = u32;
-}
-impl CustomInt<{ ControlTable::MinPositionLimit as usize }> for () {
-    type Ty = u32;
-}
-impl CustomInt<{ ControlTable::StartupConfiguration as usize }> for () {
-    type Ty = u8;
-}
-impl CustomInt<{ ControlTable::PWMSlope as usize }> for () {
-    type Ty = u8;
-}
-impl CustomInt<{ ControlTable::Shutdown as usize }> for () {
-    type Ty = u8;
-}
-impl CustomInt<{ ControlTable::TorqueEnable as usize }> for () {
-    type Ty = u8;
-}
-impl CustomInt<{ ControlTable::LED as usize }> for () {
-    type Ty = u8;
-}
-impl CustomInt<{ ControlTable::StatusReturnLevel as usize }> for () {
-    type Ty = u8;
-}
-impl CustomInt<{ ControlTable::RegisteredInstruction as usize }> for () {
-    type Ty = u8;
-}
-impl CustomInt<{ ControlTable::HardwareErrorStatus as usize }> for () {
-    type Ty = u8;
-}
-impl CustomInt<{ ControlTable::VelocityIGain as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::VelocityPgain as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::PositionDGain as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::PositionIGain as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::PositionPGain as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::Feedforward2ndGain as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::Feedforward1stGain as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::BusWatchdog as usize }> for () {
-    type Ty = u8;
-}
-impl CustomInt<{ ControlTable::GoalPWM as usize }> for () {
-    type Ty = i16;
-}
-impl CustomInt<{ ControlTable::GoalCurrent as usize }> for () {
-    type Ty = i16;
-}
-impl CustomInt<{ ControlTable::GoalVelocity as usize }> for () {
-    type Ty = i32;
-}
-impl CustomInt<{ ControlTable::ProfileAccleration as usize }> for () {
-    type Ty = u32;
-}
-impl CustomInt<{ ControlTable::ProfileVelocity as usize }> for () {
-    type Ty = u32;
-}
-impl CustomInt<{ ControlTable::GoalPosition as usize }> for () {
-    type Ty = i32;
-}
-impl CustomInt<{ ControlTable::RealtimeTick as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::Moving as usize }> for () {
-    type Ty = u8;
-}
-impl CustomInt<{ ControlTable::MovingStatus as usize }> for () {
-    type Ty = u8;
-}
-impl CustomInt<{ ControlTable::PresentPWM as usize }> for () {
-    type Ty = i16;
-}
-impl CustomInt<{ ControlTable::PresentCurrent as usize }> for () {
-    type Ty = i16;
-}
-impl CustomInt<{ ControlTable::PresentVelocity as usize }> for () {
-    type Ty = i32;
-}
-impl CustomInt<{ ControlTable::PresentPosition as usize }> for () {
-    type Ty = i32;
-}
-impl CustomInt<{ ControlTable::VelocityTrajectory as usize }> for () {
-    type Ty = i32;
-}
-impl CustomInt<{ ControlTable::PositionTrajectory as usize }> for () {
-    type Ty = i32;
-}
-impl CustomInt<{ ControlTable::PresentInputVoltage as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::PresentTemperature as usize }> for () {
-    type Ty = u8;
-}
-impl CustomInt<{ ControlTable::BackupReady as usize }> for () {
-    type Ty = u8;
-}
-impl CustomInt<{ ControlTable::IndirectAddress1 as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::IndirectAddress2 as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::IndirectAddress3 as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::IndirectAddress4 as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::IndirectAddress5 as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::IndirectAddress6 as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::IndirectAddress7 as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::IndirectAddress8 as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::IndirectAddress9 as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::IndirectAddress10 as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::IndirectAddress11 as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::IndirectAddress12 as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::IndirectAddress13 as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::IndirectAddress14 as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::IndirectAddress15 as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::IndirectAddress16 as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::IndirectAddress17 as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::IndirectAddress18 as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::IndirectAddress19 as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::IndirectAddress20 as usize }> for () {
-    type Ty = u16;
-}
-impl CustomInt<{ ControlTable::IndirectData1 as usize }> for () {
-    type Ty = u8;
+
+#[doc(hidden)]
+pub trait RegisterType {
+    const DATA_TYPE: DataType;
 }
-impl CustomInt<{ ControlTable::IndirectData2 as usize }> for () {
-    type Ty = u8;
+impl RegisterType for u8 {
+    const DATA_TYPE: DataType = DataType::U8;
 }
-impl CustomInt<{ ControlTable::IndirectData3 as usize }> for () {
-    type Ty = u8;
+impl RegisterType for u16 {
+    const DATA_TYPE: DataType = DataType::U16;
 }
-impl CustomInt<{ ControlTable::IndirectData4 as usize }> for () {
-    type Ty = u8;
+impl RegisterType for i16 {
+    const DATA_TYPE: DataType = DataType::I16;
 }
-impl CustomInt<{ ControlTable::IndirectData5 as usize }> for () {
-    type Ty = u8;
+impl RegisterType for u32 {
+    const DATA_TYPE: DataType = DataType::U32;
 }
-impl CustomInt<{ ControlTable::IndirectData6 as usize }> for () {
-    type Ty = u8;
+impl RegisterType for i32 {
+    const DATA_TYPE: DataType = DataType::I32;
 }
-impl CustomInt<{ ControlTable::IndirectData7 as usize }> for () {
-    type Ty = u8;
+
+/// Registers of a table defined with `dynamixel_control_table!` outside this crate.
+///
+/// Pass the table to `DynamixelProtocolHandler::add_register_table` so that writes from the
+/// master are checked against its access and range.
+pub trait RegisterTable: Sized + 'static {
+    /// All registers in the order of the address.
+    const ALL: &'static [Self];
+    fn spec(&self) -> RegisterSpec;
+    /// Range of the value that can be written.
+    fn range(&self) -> core::ops::RangeInclusive<i64>;
 }
-impl CustomInt<{ ControlTable::IndirectData8 as usize }> for () {
-    type Ty = u8;
+
+/// Defines a control table from one line per register.
+///
+/// `Name(accessor): address, type, access, default, min..=max;`
+///
+/// The enum and the address/size/access/default/range metadata are generated from the line, so
+/// they cannot disagree with each other. The size is taken from the type. The typed accessors are
+/// generated as the two traits named after the enum, implemented for `R` and `W` of any layout.
+/// The enum implements `RegisterTable`, so the access and range are enforced on writes from the
+/// master once it is added with `DynamixelProtocolHandler::add_register_table`.
+///
+/// ```
+/// use dynamixel_f_rs::control_table::BitsW;
+/// use dynamixel_f_rs::dynamixel_control_table;
+/// use dynamixel_f_rs::layout::Access;
+/// use dynamixel_f_rs::ControlTableData;
+///
+/// dynamixel_control_table! {
+///     /// Registers of a gripper placed in the unused area of XC330.
+///     #[derive(Clone, Copy, Debug, PartialEq)]
+///     pub enum GripperTable {
+///         GripForce(grip_force): 224, u16, ReadWrite, 100, 0..=1000;
+///         GripState(grip_state): 226, u8, ReadOnly, 0, 0..=2;
+///     }
+///     pub trait GripperRead for R;
+///     pub trait GripperWrite for W;
+/// }
+///
+/// let ctd = ControlTableData::new();
+/// ctd.modify(|_, w| w.grip_force(500).id().bits(2));
+/// assert_eq!(ctd.read().grip_force(), 500);
+/// assert_eq!(ctd.read().id(), 2);
+/// assert_eq!(GripperTable::GripForce.to_size(), 2);
+/// assert_eq!(GripperTable::GripState.access(), Access::ReadOnly);
+/// assert!(!GripperTable::GripForce.range().contains(&1001));
+/// ```
+#[macro_export]
+macro_rules! dynamixel_control_table {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $table:ident {
+            $(
+                $name:ident($accessor:ident): $address:literal, $ty:ident, $access:ident,
+                $default:literal, $min:literal..=$max:literal;
+            )*
+        }
+        $(#[$read_meta:meta])*
+        $read_vis:vis trait $read:ident for R;
+        $(#[$write_meta:meta])*
+        $write_vis:vis trait $write:ident for W;
+    ) => {
+        $crate::dynamixel_control_table! {
+            @table
+            $(#[$meta])*
+            $vis enum $table {
+                $($name: $address, $ty, $access, $default, $min..=$max;)*
+            }
+        }
+
+        impl $crate::control_table::RegisterTable for $table {
+            const ALL: &'static [$table] = $table::ALL;
+
+            fn spec(&self) -> $crate::layout::RegisterSpec {
+                $crate::layout::RegisterSpec {
+                    address: self.to_address(),
+                    size: self.to_size(),
+                    access: self.access(),
+                    data_type: self.data_type(),
+                }
+            }
+
+            fn range(&self) -> ::core::ops::RangeInclusive<i64> {
+                $table::range(self)
+            }
+        }
+
+        $(#[$read_meta])*
+        $read_vis trait $read {
+            $(fn $accessor(&self) -> $ty;)*
+        }
+
+        impl<L: $crate::layout::ControlTableLayout> $read for $crate::control_table::R<L> {
+            $(
+                fn $accessor(&self) -> $ty {
+                    let mut bytes = [0; ::core::mem::size_of::<$ty>()];
+                    // 画像に収まらないレジスタは0として読む
+                    let bits = self.bits();
+                    if let Some(b) = bits.as_ref().get($address..$address + bytes.len()) {
+                        bytes.copy_from_slice(b);
+                    }
+                    <$ty>::from_le_bytes(bytes)
+                }
+            )*
+        }
+
+        $(#[$write_meta])*
+        $write_vis trait $write {
+            $(fn $accessor(&mut self, value: $ty) -> &mut Self;)*
+        }
+
+        impl<L: $crate::layout::ControlTableLayout> $write for $crate::control_table::W<L> {
+            $(
+                fn $accessor(&mut self, value: $ty) -> &mut Self {
+                    self.bytes($address, &value.to_le_bytes())
+                }
+            )*
+        }
+    };
+    // このクレートのControlTable: アドレスは機種のレイアウトから引く
+    (
+        @layout
+        $(#[$meta:meta])*
+        pub enum $table:ident {
+            $(
+                $name:ident($accessor:ident): $address:literal, $ty:ident, $access:ident,
+                $default:literal, $min:literal..=$max:literal;
+            )*
+        }
+    ) => {
+        $crate::dynamixel_control_table! {
+            @table
+            $(#[$meta])*
+            pub enum $table {
+                $($name: $address, $ty, $access, $default, $min..=$max;)*
+            }
+        }
+
+        $(
+            impl CustomInt<{ $table::$name as usize }> for () {
+                type Ty = $ty;
+            }
+        )*
+
+        impl<L: ControlTableLayout> R<L> {
+            $(
+                pub fn $accessor(&self) -> $ty {
+                    self.to_data($table::$name)
+                }
+            )*
+        }
+
+        impl<L: ControlTableLayout> W<L> {
+            $(
+                pub fn $accessor(&mut self) -> BaseW<'_, $ty, L> {
+                    BaseW {
+                        w: self,
+                        ct: $table::$name,
+                        _type: marker::PhantomData,
+                    }
+                }
+            )*
+        }
+    };
+    (
+        @table
+        $(#[$meta:meta])*
+        $vis:vis enum $table:ident {
+            $(
+                $name:ident: $address:literal, $ty:ident, $access:ident,
+                $default:literal, $min:literal..=$max:literal;
+            )*
+        }
+    ) => {
+        $(#[$meta])*
+        $vis enum $table {
+            $($name,)*
+        }
+
+        // ControlTableの既存のAPIに合わせてCopyでも&selfを取る
+        #[allow(clippy::wrong_self_convention)]
+        impl $table {
+            /// All registers in the order of the address.
+            pub const ALL: &'static [$table] = &[$($table::$name,)*];
+
+            pub fn to_address(&self) -> u16 {
+                match self {
+                    $($table::$name => $address,)*
+                }
+            }
+
+            pub fn to_size(&self) -> u16 {
+                match self {
+                    $($table::$name => ::core::mem::size_of::<$ty>() as u16,)*
+                }
+            }
+
+            pub fn access(&self) -> $crate::layout::Access {
+                match self {
+                    $($table::$name => $crate::layout::Access::$access,)*
+                }
+            }
+
+            pub fn data_type(&self) -> $crate::layout::DataType {
+                match self {
+                    $(
+                        $table::$name => {
+                            <$ty as $crate::control_table::RegisterType>::DATA_TYPE
+                        }
+                    )*
+                }
+            }
+
+            /// Factory default value.
+            pub fn default_value(&self) -> i64 {
+                match self {
+                    $($table::$name => $default,)*
+                }
+            }
+
+            /// Range of the value that can be written.
+            pub fn range(&self) -> ::core::ops::RangeInclusive<i64> {
+                match self {
+                    $($table::$name => $min..=$max,)*
+                }
+            }
+        }
+    };
 }
-impl CustomInt<{ ControlTable::IndirectData9 as usize }> for () {
-    type Ty = u8;
+
+dynamixel_control_table! {
+    @layout
+    /// XC330相当のデータ量を持つControlTableを定義する
+    #[allow(dead_code)]
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum ControlTable {
+        ModelNumber(model_number): 0, u16, ReadOnly, 1240, 0..=65535;
+        ModelInformation(model_information): 2, u32, ReadOnly, 0, 0..=4294967295;
+        FirmwareVersion(firmware_version): 6, u8, ReadOnly, 0, 0..=255;
+        ID(id): 7, u8, ReadWrite, 1, 0..=253;
+        BaudRate(baud_rate): 8, u8, ReadWrite, 1, 0..=7;
+        ReturnDelayTime(return_delay_time): 9, u8, ReadWrite, 250, 0..=254;
+        DriveMode(drive_mode): 10, u8, ReadWrite, 0, 0..=13;
+        OperatingMode(operating_mode): 11, u8, ReadWrite, 3, 0..=16;
+        SecondaryID(secondary_id): 12, u8, ReadWrite, 255, 0..=255;
+        ProtocolType(protocol_type): 13, u8, ReadWrite, 2, 2..=2;
+        HomingOffset(homing_offset): 20, i32, ReadWrite, 0, -1044479..=1044479;
+        MovingThreshold(moving_threshold): 24, u32, ReadWrite, 10, 0..=1023;
+        TemperatureLimit(temperature_limit): 31, u8, ReadWrite, 70, 0..=100;
+        MaxVoltageLimit(max_voltage_limit): 32, u16, ReadWrite, 70, 31..=70;
+        MinVoltageLimit(min_voltage_limit): 34, u16, ReadWrite, 35, 31..=70;
+        PWMLimit(pwm_limit): 36, u16, ReadWrite, 885, 0..=885;
+        CurrentLimit(current_limit): 38, u16, ReadWrite, 1750, 0..=1750;
+        VelocityLimit(velocity_limit): 44, u32, ReadWrite, 445, 0..=2047;
+        MaxPositionLimit(max_position_limit): 48, u32, ReadWrite, 4095, 0..=4095;
+        MinPositionLimit(min_position_limit): 52, u32, ReadWrite, 0, 0..=4095;
+        StartupConfiguration(startup_configuration): 60, u8, ReadWrite, 0, 0..=3;
+        PWMSlope(pwm_slope): 62, u8, ReadWrite, 140, 1..=255;
+        Shutdown(shutdown): 63, u8, ReadWrite, 53, 0..=255;
+        TorqueEnable(torque_enable): 64, u8, ReadWrite, 0, 0..=1;
+        LED(led): 65, u8, ReadWrite, 0, 0..=1;
+        StatusReturnLevel(status_return_level): 68, u8, ReadWrite, 2, 0..=2;
+        RegisteredInstruction(registered_instruction): 69, u8, ReadOnly, 0, 0..=1;
+        HardwareErrorStatus(hardware_error_status): 70, u8, ReadOnly, 0, 0..=255;
+        VelocityIGain(velocity_igain): 76, u16, ReadWrite, 1000, 0..=16383;
+        VelocityPgain(velocity_pgain): 78, u16, ReadWrite, 100, 0..=16383;
+        PositionDGain(position_dgain): 80, u16, ReadWrite, 0, 0..=16383;
+        PositionIGain(position_igain): 82, u16, ReadWrite, 0, 0..=16383;
+        PositionPGain(position_pgain): 84, u16, ReadWrite, 400, 0..=16383;
+        Feedforward2ndGain(feedforward2nd_gain): 88, u16, ReadWrite, 0, 0..=16383;
+        Feedforward1stGain(feedforward1st_gain): 90, u16, ReadWrite, 0, 0..=16383;
+        BusWatchdog(bus_watchdog): 98, u8, ReadWrite, 0, 0..=127;
+        GoalPWM(goal_pwm): 100, i16, ReadWrite, 0, -885..=885;
+        GoalCurrent(goal_current): 102, i16, ReadWrite, 0, -1750..=1750;
+        GoalVelocity(goal_velocity): 104, i32, ReadWrite, 0, -2047..=2047;
+        ProfileAccleration(profile_accleration): 108, u32, ReadWrite, 0, 0..=32767;
+        ProfileVelocity(profile_velocity): 112, u32, ReadWrite, 0, 0..=32767;
+        GoalPosition(goal_position): 116, i32, ReadWrite, 0, -2147483648..=2147483647;
+        RealtimeTick(realtime_tick): 120, u16, ReadOnly, 0, 0..=32767;
+        Moving(moving): 122, u8, ReadOnly, 0, 0..=1;
+        MovingStatus(moving_status): 123, u8, ReadOnly, 0, 0..=255;
+        PresentPWM(present_pwm): 124, i16, ReadOnly, 0, -32768..=32767;
+        PresentCurrent(present_current): 126, i16, ReadOnly, 0, -32768..=32767;
+        PresentVelocity(present_velocity): 128, i32, ReadOnly, 0, -2147483648..=2147483647;
+        PresentPosition(present_position): 132, i32, ReadOnly, 0, -2147483648..=2147483647;
+        VelocityTrajectory(velocity_trajectory): 136, i32, ReadOnly, 0, -2147483648..=2147483647;
+        PositionTrajectory(position_trajectory): 140, i32, ReadOnly, 0, -2147483648..=2147483647;
+        PresentInputVoltage(present_input_voltage): 144, u16, ReadOnly, 0, 0..=65535;
+        PresentTemperature(present_temperature): 146, u8, ReadOnly, 0, 0..=255;
+        BackupReady(backup_ready): 147, u8, ReadOnly, 0, 0..=1;
+        IndirectAddress1(indirect_address1): 168, u16, ReadWrite, 208, 64..=227;
+        IndirectAddress2(indirect_address2): 170, u16, ReadWrite, 209, 64..=227;
+        IndirectAddress3(indirect_address3): 172, u16, ReadWrite, 210, 64..=227;
+        IndirectAddress4(indirect_address4): 174, u16, ReadWrite, 211, 64..=227;
+        IndirectAddress5(indirect_address5): 176, u16, ReadWrite, 212, 64..=227;
+        IndirectAddress6(indirect_address6): 178, u16, ReadWrite, 213, 64..=227;
+        IndirectAddress7(indirect_address7): 180, u16, ReadWrite, 214, 64..=227;
+        IndirectAddress8(indirect_address8): 182, u16, ReadWrite, 215, 64..=227;
+        IndirectAddress9(indirect_address9): 184, u16, ReadWrite, 216, 64..=227;
+        IndirectAddress10(indirect_address10): 186, u16, ReadWrite, 217, 64..=227;
+        IndirectAddress11(indirect_address11): 188, u16, ReadWrite, 218, 64..=227;
+        IndirectAddress12(indirect_address12): 190, u16, ReadWrite, 219, 64..=227;
+        IndirectAddress13(indirect_address13): 192, u16, ReadWrite, 220, 64..=227;
+        IndirectAddress14(indirect_address14): 194, u16, ReadWrite, 221, 64..=227;
+        IndirectAddress15(indirect_address15): 196, u16, ReadWrite, 222, 64..=227;
+        IndirectAddress16(indirect_address16): 198, u16, ReadWrite, 223, 64..=227;
+        IndirectAddress17(indirect_address17): 200, u16, ReadWrite, 224, 64..=227;
+        IndirectAddress18(indirect_address18): 202, u16, ReadWrite, 225, 64..=227;
+        IndirectAddress19(indirect_address19): 204, u16, ReadWrite, 226, 64..=227;
+        IndirectAddress20(indirect_address20): 206, u16, ReadWrite, 227, 64..=227;
+        IndirectData1(indirect_data1): 208, u8, ReadWrite, 0, 0..=255;
+        IndirectData2(indirect_data2): 209, u8, ReadWrite, 0, 0..=255;
+        IndirectData3(indirect_data3): 210, u8, ReadWrite, 0, 0..=255;
+        IndirectData4(indirect_data4): 211, u8, ReadWrite, 0, 0..=255;
+        IndirectData5(indirect_data5): 212, u8, ReadWrite, 0, 0..=255;
+        IndirectData6(indirect_data6): 213, u8, ReadWrite, 0, 0..=255;
+        IndirectData7(indirect_data7): 214, u8, ReadWrite, 0, 0..=255;
+        IndirectData8(indirect_data8): 215, u8, ReadWrite, 0, 0..=255;
+        IndirectData9(indirect_data9): 216, u8, ReadWrite, 0, 0..=255;
+        IndirectData10(indirect_data10): 217, u8, ReadWrite, 0, 0..=255;
+        IndirectData11(indirect_data11): 218, u8, ReadWrite, 0, 0..=255;
+        IndirectData12(indirect_data12): 219, u8, ReadWrite, 0, 0..=255;
+        IndirectData13(indirect_data13): 220, u8, ReadWrite, 0, 0..=255;
+        IndirectData14(indirect_data14): 221, u8, ReadWrite, 0, 0..=255;
+        IndirectData15(indirect_data15): 222, u8, ReadWrite, 0, 0..=255;
+        IndirectData16(indirect_data16): 223, u8, ReadWrite, 0, 0..=255;
+        IndirectData17(indirect_data17): 224, u8, ReadWrite, 0, 0..=255;
+        IndirectData18(indirect_data18): 225, u8, ReadWrite, 0, 0..=255;
+        IndirectData19(indirect_data19): 226, u8, ReadWrite, 0, 0..=255;
+        IndirectData20(indirect_data20): 227, u8, ReadWrite, 0, 0..=255;
+    }
 }
-impl CustomInt<{ ControlTable::IndirectData10 as usize }> for () {
-    type Ty = u8;
+
+pub struct ControlTableData<L: ControlTableLayout = XC330> {
+    value: Cell<L::Image>,
+    // value: [Cell<u8>; 8],だと要素ごとに.getしないといけないのが大変そうなので上で進めてみる
 }
-impl CustomInt<{ ControlTable::IndirectData11 as usize }> for () {
-    type Ty = u8;
+
+impl ControlTableData<XC330> {
+    pub fn new() -> Self {
+        Self::with_layout()
+    }
+    /// Table filled with the factory default values of the `M` model,
+    /// e.g. `ControlTableData::with_defaults::<XM430>()`.
+    pub fn with_defaults<M: ControlTableLayout>() -> ControlTableData<M> {
+        let ctd = ControlTableData::<M>::with_layout();
+        ctd.write(|w| w.defaults());
+        ctd
+    }
 }
-impl CustomInt<{ ControlTable::IndirectData12 as usize }> for () {
-    type Ty = u8;
+
+impl<L: ControlTableLayout> ControlTableData<L> {
+    /// Zero filled table of the `L` model, e.g. `ControlTableData::<XM430>::with_layout()`.
+    pub fn with_layout() -> Self {
+        Self {
+            value: Cell::new(L::EMPTY),
+        }
+    }
+    pub fn read(&self) -> R<L> {
+        R {
+            bits: self.value.get(),
+        }
+    }
+    pub fn modify<F>(&self, f: F)
+    where
+        for<'w> F: FnOnce(&R<L>, &'w mut W<L>) -> &'w mut W<L>,
+    {
+        let bits = self.value.get();
+        self.value.set(f(&R { bits }, &mut W { bits }).bits);
+    }
+
+    pub fn write<F>(&self, f: F)
+    where
+        F: FnOnce(&mut W<L>) -> &mut W<L>,
+    {
+        self.value.set(f(&mut W { bits: L::EMPTY }).bits);
+    }
 }
-impl CustomInt<{ ControlTable::IndirectData13 as usize }> for () {
-    type Ty = u8;
+
+impl<L: ControlTableLayout> Default for ControlTableData<L> {
+    fn default() -> Self {
+        Self::with_layout()
+    }
 }
-impl CustomInt<{ ControlTable::IndirectData14 as usize }> for () {
-    type Ty = u8;
+
+/// `ControlTableData` which can be shared between the main loop and interrupts.
+///
+/// Every access is done in a critical section, so a multi-byte register is never seen half
+/// written. `get` and `set` copy only the register instead of the whole table, so they are cheap
+/// enough for the control interrupt.
+///
+/// Put it in a `static` and give the reference to `DynamixelProtocolHandler::new`.
+pub struct SharedControlTableData<L: ControlTableLayout = XC330> {
+    value: Mutex<Cell<L::Image>>,
 }
-impl CustomInt<{ ControlTable::IndirectData15 as usize }> for () {
-    type Ty = u8;
+
+impl SharedControlTableData<XC330> {
+    pub const fn new() -> Self {
+        Self::with_layout()
+    }
 }
-impl CustomInt<{ ControlTable::IndirectData16 as usize }> for () {
-    type Ty = u8;
+
+impl<L: ControlTableLayout> SharedControlTableData<L> {
+    /// Zero filled table of the `L` model, e.g. `SharedControlTableData::<XM430>::with_layout()`.
+    pub const fn with_layout() -> Self {
+        Self {
+            value: Mutex::new(Cell::new(L::EMPTY)),
+        }
+    }
+    pub fn read(&self) -> R<L> {
+        critical_section::with(|cs| R {
+            bits: self.value.borrow(cs).get(),
+        })
+    }
+    pub fn modify<F>(&self, f: F)
+    where
+        for<'w> F: FnOnce(&R<L>, &'w mut W<L>) -> &'w mut W<L>,
+    {
+        critical_section::with(|cs| {
+            let value = self.value.borrow(cs);
+            let bits = value.get();
+            value.set(f(&R { bits }, &mut W { bits }).bits);
+        })
+    }
+    pub fn write<F>(&self, f: F)
+    where
+        F: FnOnce(&mut W<L>) -> &mut W<L>,
+    {
+        critical_section::with(|cs| self.value.borrow(cs).set(f(&mut W { bits: L::EMPTY }).bits))
+    }
+    /// Value of the register regardless of the type, 0 if the model does not have it.
+    pub fn get(&self, ct: ControlTable) -> i64 {
+        let spec = match L::register(ct) {
+            Some(spec) => spec,
+            None => return 0,
+        };
+        let address = spec.address as usize;
+        let image = critical_section::with(|cs| self.value.borrow(cs).get());
+        let mut bytes = [0; 4];
+        bytes[..spec.size as usize]
+            .copy_from_slice(&image.as_ref()[address..address + spec.size as usize]);
+        spec.data_type.from_le_bytes(&bytes)
+    }
+    /// Writes `value` to the register regardless of the type. Upper bytes are truncated.
+    pub fn set(&self, ct: ControlTable, value: i64) {
+        let spec = match L::register(ct) {
+            Some(spec) => spec,
+            None => return,
+        };
+        let address = spec.address as usize;
+        let bytes = value.to_le_bytes();
+        critical_section::with(|cs| {
+            let value = self.value.borrow(cs);
+            let mut image = value.get();
+            image.as_mut()[address..address + spec.size as usize]
+                .copy_from_slice(&bytes[..spec.size as usize]);
+            value.set(image);
+        });
+    }
 }
-impl CustomInt<{ ControlTable::IndirectData17 as usize }> for () {
-    type Ty = u8;
+
+impl<L: ControlTableLayout> Default for SharedControlTableData<L> {
+    fn default() -> Self {
+        Self::with_layout()
+    }
 }
-impl CustomInt<{ ControlTable::IndirectData18 as usize }> for () {
-    type Ty = u8;
+
+impl<L: ControlTableLayout> From<ControlTableData<L>> for SharedControlTableData<L> {
+    fn from(ctd: ControlTableData<L>) -> Self {
+        Self {
+            value: Mutex::new(ctd.value),
+        }
+    }
 }
-impl CustomInt<{ ControlTable::IndirectData19 as usize }> for () {
-    type Ty = u8;
+
+impl<L: ControlTableLayout> ControlTableAccess for SharedControlTableData<L> {
+    type Layout = L;
+    fn read(&self) -> R<L> {
+        SharedControlTableData::read(self)
+    }
+    fn modify<F>(&self, f: F)
+    where
+        for<'w> F: FnOnce(&R<L>, &'w mut W<L>) -> &'w mut W<L>,
+    {
+        SharedControlTableData::modify(self, f)
+    }
+    fn write<F>(&self, f: F)
+    where
+        F: FnOnce(&mut W<L>) -> &mut W<L>,
+    {
+        SharedControlTableData::write(self, f)
+    }
 }
-impl CustomInt<{ ControlTable::IndirectData20 as usize }> for () {
-    type Ty = u8;
+
+// レジスタ数がビット数を超えたら配列にする
+const _: () = assert!(ControlTable::ALL.len() <= 128);
+
+/// Set of registers, e.g. the registers written by the master.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RegisterSet {
+    bits: u128,
 }
 
-#[allow(dead_code)]
-impl ControlTable {
-    pub fn to_address(&self) -> u16 {
-        match self {
-            ControlTable::ModelNumber => 0,
-            ControlTable::ModelInformation => 2,
-            ControlTable::FirmwareVersion => 6,
-            ControlTable::ID => 7,
-            ControlTable::BaudRate => 8,
-            ControlTable::ReturnDelayTime => 9,
-            ControlTable::DriveMode => 10,
-            ControlTable::OperatingMode => 11,
-            ControlTable::SecondaryID => 12,
-            ControlTable::ProtocolType => 13,
-            ControlTable::HomingOffset => 20,
-            ControlTable::MovingThreshold => 24,
-            ControlTable::TemperatureLimit => 31,
-            ControlTable::MaxVoltageLimit => 32,
-            ControlTable::MinVoltageLimit => 34,
-            ControlTable::PWMLimit => 36,
-            ControlTable::CurrentLimit => 38,
-            ControlTable::VelocityLimit => 44,
-            ControlTable::MaxPositionLimit => 48,
-            ControlTable::MinPositionLimit => 52,
-            ControlTable::StartupConfiguration => 60,
-            ControlTable::PWMSlope => 62,
-            ControlTable::Shutdown => 63,
-            ControlTable::TorqueEnable => 64,
-            ControlTable::LED => 65,
-            ControlTable::StatusReturnLevel => 68,
-            ControlTable::RegisteredInstruction => 69,
-            ControlTable::HardwareErrorStatus => 70,
-            ControlTable::VelocityIGain => 76,
-            ControlTable::VelocityPgain => 78,
-            ControlTable::PositionDGain => 80,
-            ControlTable::PositionIGain => 82,
-            ControlTable::PositionPGain => 84,
-            ControlTable::Feedforward2ndGain => 88,
-            ControlTable::Feedforward1stGain => 90,
-            ControlTable::BusWatchdog => 98,
-            ControlTable::GoalPWM => 100,
-            ControlTable::GoalCurrent => 102,
-            ControlTable::GoalVelocity => 104,
-            ControlTable::ProfileAccleration => 108,
-            ControlTable::ProfileVelocity => 112,
-            ControlTable::GoalPosition => 116,
-            ControlTable::RealtimeTick => 120,
-            ControlTable::Moving => 122,
-            ControlTable::MovingStatus => 123,
-            ControlTable::PresentPWM => 124,
-            ControlTable::PresentCurrent => 126,
-            ControlTable::PresentVelocity => 128,
-            ControlTable::PresentPosition => 132,
-            ControlTable::VelocityTrajectory => 136,
-            ControlTable::PositionTrajectory => 140,
-            ControlTable::PresentInputVoltage => 144,
-            ControlTable::PresentTemperature => 146,
-            ControlTable::BackupReady => 147,
-            ControlTable::IndirectAddress1 => 168,
-            ControlTable::IndirectAddress2 => 170,
-            ControlTable::IndirectAddress3 => 172,
-            ControlTable::IndirectAddress4 => 174,
-            ControlTable::IndirectAddress5 => 176,
-            ControlTable::IndirectAddress6 => 178,
-            ControlTable::IndirectAddress7 => 180,
-            ControlTable::IndirectAddress8 => 182,
-            ControlTable::IndirectAddress9 => 184,
-            ControlTable::IndirectAddress10 => 186,
-            ControlTable::IndirectAddress11 => 188,
-            ControlTable::IndirectAddress12 => 190,
-            ControlTable::IndirectAddress13 => 192,
-            ControlTable::IndirectAddress14 => 194,
-            ControlTable::IndirectAddress15 => 196,
-            ControlTable::IndirectAddress16 => 198,
-            ControlTable::IndirectAddress17 => 200,
-            ControlTable::IndirectAddress18 => 202,
-            ControlTable::IndirectAddress19 => 204,
-            ControlTable::IndirectAddress20 => 206,
-            ControlTable::IndirectData1 => 208,
-            ControlTable::IndirectData2 => 209,
-            ControlTable::IndirectData3 => 210,
-            ControlTable::IndirectData4 => 211,
-            ControlTable::IndirectData5 => 212,
-            ControlTable::IndirectData6 => 213,
-            ControlTable::IndirectData7 => 214,
-            ControlTable::IndirectData8 => 215,
-            ControlTable::IndirectData9 => 216,
-            ControlTable::IndirectData10 => 217,
-            ControlTable::IndirectData11 => 218,
-            ControlTable::IndirectData12 => 219,
-            ControlTable::IndirectData13 => 220,
-            ControlTable::IndirectData14 => 221,
-            ControlTable::IndirectData15 => 222,
-            ControlTable::IndirectData16 => 223,
-            ControlTable::IndirectData17 => 224,
-            ControlTable::IndirectData18 => 225,
-            ControlTable::IndirectData19 => 226,
-            ControlTable::IndirectData20 => 227,
+impl RegisterSet {
+    pub const fn new() -> Self {
+        Self { bits: 0 }
+    }
+    pub fn insert(&mut self, ct: ControlTable) {
+        self.bits |= 1 << ct as u32;
+    }
+    pub fn remove(&mut self, ct: ControlTable) {
+        self.bits &= !(1 << ct as u32);
+    }
+    pub fn contains(&self, ct: ControlTable) -> bool {
+        self.bits & (1 << ct as u32) != 0
+    }
+    pub fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+    pub fn clear(&mut self) {
+        self.bits = 0;
+    }
+    pub fn iter(&self) -> impl Iterator<Item = ControlTable> + '_ {
+        ControlTable::ALL
+            .iter()
+            .copied()
+            .filter(move |ct| self.contains(*ct))
+    }
+    /// Insert the registers of the `L` layout overlapping `address..address + length`.
+    pub fn insert_range<L: ControlTableLayout>(&mut self, address: usize, length: usize) {
+        for ct in ControlTable::ALL {
+            if let Some(spec) = L::register(*ct) {
+                let start = spec.address as usize;
+                if start < address + length && address < start + spec.size as usize {
+                    self.insert(*ct);
+                }
+            }
         }
     }
+}
 
-    pub fn to_size(&self) -> u16 {
-        match self {
-            ControlTable::ModelNumber => 2,
-            ControlTable::ModelInformation => 4,
-            ControlTable::FirmwareVersion => 1,
-            ControlTable::ID => 1,
-            ControlTable::BaudRate => 1,
-            ControlTable::ReturnDelayTime => 1,
-            ControlTable::DriveMode => 1,
-            ControlTable::OperatingMode => 1,
-            ControlTable::SecondaryID => 1,
-            ControlTable::ProtocolType => 1,
-            ControlTable::HomingOffset => 4,
-            ControlTable::MovingThreshold => 4,
-            ControlTable::TemperatureLimit => 1,
-            ControlTable::MaxVoltageLimit => 2,
-            ControlTable::MinVoltageLimit => 2,
-            ControlTable::PWMLimit => 2,
-            ControlTable::CurrentLimit => 2,
-            ControlTable::VelocityLimit => 4,
-            ControlTable::MaxPositionLimit => 4,
-            ControlTable::MinPositionLimit => 4,
-            ControlTable::StartupConfiguration => 1,
-            ControlTable::PWMSlope => 1,
-            ControlTable::Shutdown => 1,
-            ControlTable::TorqueEnable => 1,
-            ControlTable::LED => 1,
-            ControlTable::StatusReturnLevel => 1,
-            ControlTable::RegisteredInstruction => 1,
-            ControlTable::HardwareErrorStatus => 1,
-            ControlTable::VelocityIGain => 2,
-            ControlTable::VelocityPgain => 2,
-            ControlTable::PositionDGain => 2,
-            ControlTable::PositionIGain => 2,
-            ControlTable::PositionPGain => 2,
-            ControlTable::Feedforward2ndGain => 2,
-            ControlTable::Feedforward1stGain => 2,
-            ControlTable::BusWatchdog => 1,
-            ControlTable::GoalPWM => 2,
-            ControlTable::GoalCurrent => 2,
-            ControlTable::GoalVelocity => 4,
-            ControlTable::ProfileAccleration => 4,
-            ControlTable::ProfileVelocity => 4,
-            ControlTable::GoalPosition => 4,
-            ControlTable::RealtimeTick => 2,
-            ControlTable::Moving => 1,
-            ControlTable::MovingStatus => 1,
-            ControlTable::PresentPWM => 2,
-            ControlTable::PresentCurrent => 2,
-            ControlTable::PresentVelocity => 4,
-            ControlTable::PresentPosition => 4,
-            ControlTable::VelocityTrajectory => 4,
-            ControlTable::PositionTrajectory => 4,
-            ControlTable::PresentInputVoltage => 2,
-            ControlTable::PresentTemperature => 1,
-            ControlTable::BackupReady => 1,
-            ControlTable::IndirectAddress1 => 2,
-            ControlTable::IndirectAddress2 => 2,
-            ControlTable::IndirectAddress3 => 2,
-            ControlTable::IndirectAddress4 => 2,
-            ControlTable::IndirectAddress5 => 2,
-            ControlTable::IndirectAddress6 => 2,
-            ControlTable::IndirectAddress7 => 2,
-            ControlTable::IndirectAddress8 => 2,
-            ControlTable::IndirectAddress9 => 2,
-            ControlTable::IndirectAddress10 => 2,
-            ControlTable::IndirectAddress11 => 2,
-            ControlTable::IndirectAddress12 => 2,
-            ControlTable::IndirectAddress13 => 2,
-            ControlTable::IndirectAddress14 => 2,
-            ControlTable::IndirectAddress15 => 2,
-            ControlTable::IndirectAddress16 => 2,
-            ControlTable::IndirectAddress17 => 2,
-            ControlTable::IndirectAddress18 => 2,
-            ControlTable::IndirectAddress19 => 2,
-            ControlTable::IndirectAddress20 => 2,
-            ControlTable::IndirectData1 => 1,
-            ControlTable::IndirectData2 => 1,
-            ControlTable::IndirectData3 => 1,
-            ControlTable::IndirectData4 => 1,
-            ControlTable::IndirectData5 => 1,
-            ControlTable::IndirectData6 => 1,
-            ControlTable::IndirectData7 => 1,
-            ControlTable::IndirectData8 => 1,
-            ControlTable::IndirectData9 => 1,
-            ControlTable::IndirectData10 => 1,
-            ControlTable::IndirectData11 => 1,
-            ControlTable::IndirectData12 => 1,
-            ControlTable::IndirectData13 => 1,
-            ControlTable::IndirectData14 => 1,
-            ControlTable::IndirectData15 => 1,
-            ControlTable::IndirectData16 => 1,
-            ControlTable::IndirectData17 => 1,
-            ControlTable::IndirectData18 => 1,
-            ControlTable::IndirectData19 => 1,
-            ControlTable::IndirectData20 => 1,
+/// Access to the whole control table.
+///
+/// Implemented by `ControlTableData` and `SharedControlTableData`, so that
+/// `DynamixelProtocolHandler` can use either of them.
+pub trait ControlTableAccess {
+    type Layout: ControlTableLayout;
+    fn read(&self) -> R<Self::Layout>;
+    fn modify<F>(&self, f: F)
+    where
+        for<'w> F: FnOnce(&R<Self::Layout>, &'w mut W<Self::Layout>) -> &'w mut W<Self::Layout>;
+    fn write<F>(&self, f: F)
+    where
+        F: FnOnce(&mut W<Self::Layout>) -> &mut W<Self::Layout>;
+
+    /// Copy of the whole table to be edited and published with `commit`.
+    fn snapshot(&self) -> Snapshot<Self::Layout> {
+        let bits = self.read().bits();
+        Snapshot {
+            base: bits,
+            w: W { bits },
         }
     }
+
+    /// Apply all changes made to `snapshot` at once.
+    ///
+    /// Only the registers changed in the snapshot are written, so writes made to the table after
+    /// the snapshot was taken, e.g. by the parser, are kept. A register is written as a whole, so
+    /// it is never a mix of the two values.
+    fn commit(&self, snapshot: &Snapshot<Self::Layout>) {
+        self.modify(|_, w| {
+            let base = snapshot.base.as_ref();
+            let new = snapshot.w.bits.as_ref();
+            let bits = w.bits.as_mut();
+            // レジスタに属さない領域はbyte単位
+            for (i, b) in bits.iter_mut().enumerate() {
+                if new[i] != base[i] {
+                    *b = new[i];
+                }
+            }
+            for ct in ControlTable::ALL {
+                if let Some(spec) = Self::Layout::register(*ct) {
+                    let range = spec.address as usize..(spec.address + spec.size) as usize;
+                    if new[range.clone()] != base[range.clone()] {
+                        bits[range.clone()].copy_from_slice(&new[range]);
+                    }
+                }
+            }
+            w
+        });
+    }
 }
 
-const CONTROL_TABLE_SIZE: usize = 231; // Dynamixel Wizardが大きめに読むのでアドレス終端の227よりも大きくする
-type Ux = [u8; CONTROL_TABLE_SIZE];
-pub struct ControlTableData {
-    value: Cell<Ux>,
-    // value: [Cell<u8>; 8],だと要素ごとに.getしないといけないのが大変そうなので上で進めてみる
+/// Consistent copy of the control table, taken by `ControlTableAccess::snapshot`.
+///
+/// The control loop reads all goal values from one snapshot and writes all present values to it,
+/// then publishes them together with `ControlTableAccess::commit`.
+pub struct Snapshot<L: ControlTableLayout = XC330> {
+    base: L::Image,
+    w: W<L>,
 }
 
-impl ControlTableData {
-    pub fn new() -> Self {
-        Self {
-            value: Cell::new([0; CONTROL_TABLE_SIZE]),
-        }
+impl<L: ControlTableLayout> Snapshot<L> {
+    /// Values of the snapshot including the changes made to it.
+    pub fn read(&self) -> R<L> {
+        R { bits: self.w.bits }
     }
-    pub fn read(&self) -> R {
-        R {
-            bits: self.value.get(),
-        }
+    pub fn write(&mut self) -> &mut W<L> {
+        &mut self.w
     }
-    pub fn modify<F>(&self, f: F)
+}
+
+impl<L: ControlTableLayout> ControlTableAccess for ControlTableData<L> {
+    type Layout = L;
+    fn read(&self) -> R<L> {
+        ControlTableData::read(self)
+    }
+    fn modify<F>(&self, f: F)
     where
-        for<'w> F: FnOnce(&R, &'w mut W) -> &'w mut W,
+        for<'w> F: FnOnce(&R<L>, &'w mut W<L>) -> &'w mut W<L>,
     {
-        let bits = self.value.get();
-        self.value.set(f(&R { bits }, &mut W { bits }).bits);
+        ControlTableData::modify(self, f)
     }
+    fn write<F>(&self, f: F)
+    where
+        F: FnOnce(&mut W<L>) -> &mut W<L>,
+    {
+        ControlTableData::write(self, f)
+    }
+}
 
-    pub fn write<F>(&self, f: F)
+// 割り込みと共有するためにstaticな参照で持てるようにする
+impl<T: ControlTableAccess> ControlTableAccess for &T {
+    type Layout = T::Layout;
+    fn read(&self) -> R<T::Layout> {
+        (**self).read()
+    }
+    fn modify<F>(&self, f: F)
     where
-        F: FnOnce(&mut W) -> &mut W,
+        for<'w> F: FnOnce(&R<T::Layout>, &'w mut W<T::Layout>) -> &'w mut W<T::Layout>,
     {
-        self.value.set(
-            f(&mut W {
-                bits: [0; CONTROL_TABLE_SIZE],
-            })
-            .bits,
-        );
+        (**self).modify(f)
+    }
+    fn write<F>(&self, f: F)
+    where
+        F: FnOnce(&mut W<T::Layout>) -> &mut W<T::Layout>,
+    {
+        (**self).write(f)
     }
 }
 
@@ -631,1423 +683,184 @@ impl ControlTableData {
 ///
 /// Result of the `read` methods of registers. Also used as a closure argument in the `modify`
 /// method.
-pub struct R {
-    bits: Ux,
+pub struct R<L: ControlTableLayout = XC330> {
+    bits: L::Image,
 }
 
 trait ParseData<T> {
     fn to_data(&self, ct: ControlTable) -> T;
 }
 
-impl ParseData<u8> for R {
+impl<L: ControlTableLayout> R<L> {
+    // 機種に無いレジスタは0として読む
+    fn register_bytes<const S: usize>(&self, ct: ControlTable) -> [u8; S] {
+        let mut bytes = [0; S];
+        if let Some(spec) = L::register(ct) {
+            let address = spec.address as usize;
+            bytes.copy_from_slice(&self.bits.as_ref()[address..address + S]);
+        }
+        bytes
+    }
+}
+
+impl<L: ControlTableLayout> ParseData<u8> for R<L> {
     fn to_data(&self, ct: ControlTable) -> u8 {
-        self.bits[ct.to_address() as usize]
+        u8::from_le_bytes(self.register_bytes(ct))
     }
 }
-impl ParseData<u16> for R {
+impl<L: ControlTableLayout> ParseData<u16> for R<L> {
     fn to_data(&self, ct: ControlTable) -> u16 {
-        u16::from_le_bytes([
-            self.bits[ct.to_address() as usize],
-            self.bits[ct.to_address() as usize + 1],
-        ])
+        u16::from_le_bytes(self.register_bytes(ct))
     }
 }
 
-impl ParseData<i16> for R {
+impl<L: ControlTableLayout> ParseData<i16> for R<L> {
     fn to_data(&self, ct: ControlTable) -> i16 {
-        i16::from_le_bytes([
-            self.bits[ct.to_address() as usize],
-            self.bits[ct.to_address() as usize + 1],
-        ])
+        i16::from_le_bytes(self.register_bytes(ct))
     }
 }
 
-impl ParseData<u32> for R {
+impl<L: ControlTableLayout> ParseData<u32> for R<L> {
     fn to_data(&self, ct: ControlTable) -> u32 {
-        u32::from_le_bytes([
-            self.bits[ct.to_address() as usize],
-            self.bits[ct.to_address() as usize + 1],
-            self.bits[ct.to_address() as usize + 2],
-            self.bits[ct.to_address() as usize + 3],
-        ])
+        u32::from_le_bytes(self.register_bytes(ct))
     }
 }
 
-impl ParseData<i32> for R {
+impl<L: ControlTableLayout> ParseData<i32> for R<L> {
     fn to_data(&self, ct: ControlTable) -> i32 {
-        i32::from_le_bytes([
-            self.bits[ct.to_address() as usize],
-            self.bits[ct.to_address() as usize + 1],
-            self.bits[ct.to_address() as usize + 2],
-            self.bits[ct.to_address() as usize + 3],
-        ])
+        i32::from_le_bytes(self.register_bytes(ct))
     }
 }
 
-impl R {
+impl<L: ControlTableLayout> R<L> {
     /// Reads raw bits from register.
     #[inline(always)]
-    pub fn bits(&self) -> Ux {
+    pub fn bits(&self) -> L::Image {
         self.bits
     }
-    pub fn model_number(&self) -> <() as CustomInt<{ ControlTable::ModelNumber as usize }>>::Ty {
-        self.to_data(ControlTable::ModelNumber)
-    }
-    pub fn model_information(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::ModelInformation as usize }>>::Ty {
-        self.to_data(ControlTable::ModelInformation)
-    }
-    pub fn firmware_version(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::FirmwareVersion as usize }>>::Ty {
-        self.to_data(ControlTable::FirmwareVersion)
-    }
-    pub fn id(&self) -> <() as CustomInt<{ ControlTable::ID as usize }>>::Ty {
-        self.to_data(ControlTable::ID)
-    }
-    pub fn baud_rate(&self) -> <() as CustomInt<{ ControlTable::BaudRate as usize }>>::Ty {
-        self.to_data(ControlTable::BaudRate)
-    }
-    pub fn return_delay_time(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::ReturnDelayTime as usize }>>::Ty {
-        self.to_data(ControlTable::ReturnDelayTime)
-    }
-    pub fn drive_mode(&self) -> <() as CustomInt<{ ControlTable::DriveMode as usize }>>::Ty {
-        self.to_data(ControlTable::DriveMode)
-    }
-    pub fn operating_mode(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::OperatingMode as usize }>>::Ty {
-        self.to_data(ControlTable::OperatingMode)
-    }
-    pub fn secondary_id(&self) -> <() as CustomInt<{ ControlTable::SecondaryID as usize }>>::Ty {
-        self.to_data(ControlTable::SecondaryID)
-    }
-    pub fn protocol_type(&self) -> <() as CustomInt<{ ControlTable::ProtocolType as usize }>>::Ty {
-        self.to_data(ControlTable::ProtocolType)
-    }
-    pub fn homing_offset(&self) -> <() as CustomInt<{ ControlTable::HomingOffset as usize }>>::Ty {
-        self.to_data(ControlTable::HomingOffset)
-    }
-    pub fn moving_threshold(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::MovingThreshold as usize }>>::Ty {
-        self.to_data(ControlTable::MovingThreshold)
-    }
-    pub fn temperature_limit(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::TemperatureLimit as usize }>>::Ty {
-        self.to_data(ControlTable::TemperatureLimit)
-    }
-    pub fn max_voltage_limit(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::MaxVoltageLimit as usize }>>::Ty {
-        self.to_data(ControlTable::MaxVoltageLimit)
-    }
-    pub fn min_voltage_limit(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::MinVoltageLimit as usize }>>::Ty {
-        self.to_data(ControlTable::MinVoltageLimit)
-    }
-    pub fn pwm_limit(&self) -> <() as CustomInt<{ ControlTable::PWMLimit as usize }>>::Ty {
-        self.to_data(ControlTable::PWMLimit)
-    }
-    pub fn current_limit(&self) -> <() as CustomInt<{ ControlTable::CurrentLimit as usize }>>::Ty {
-        self.to_data(ControlTable::CurrentLimit)
-    }
-    pub fn velocity_limit(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::VelocityLimit as usize }>>::Ty {
-        self.to_data(ControlTable::VelocityLimit)
-    }
-    pub fn max_position_limit(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::MaxPositionLimit as usize }>>::Ty {
-        self.to_data(ControlTable::MaxPositionLimit)
-    }
-    pub fn min_position_limit(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::MinPositionLimit as usize }>>::Ty {
-        self.to_data(ControlTable::MinPositionLimit)
-    }
-    pub fn startup_configuration(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::StartupConfiguration as usize }>>::Ty {
-        self.to_data(ControlTable::StartupConfiguration)
-    }
-    pub fn pwm_slope(&self) -> <() as CustomInt<{ ControlTable::PWMSlope as usize }>>::Ty {
-        self.to_data(ControlTable::PWMSlope)
-    }
-    pub fn shutdown(&self) -> <() as CustomInt<{ ControlTable::Shutdown as usize }>>::Ty {
-        self.to_data(ControlTable::Shutdown)
-    }
-    pub fn torque_enable(&self) -> <() as CustomInt<{ ControlTable::TorqueEnable as usize }>>::Ty {
-        self.to_data(ControlTable::TorqueEnable)
-    }
-    pub fn led(&self) -> <() as CustomInt<{ ControlTable::LED as usize }>>::Ty {
-        self.to_data(ControlTable::LED)
-    }
-    pub fn status_return_level(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::StatusReturnLevel as usize }>>::Ty {
-        self.to_data(ControlTable::StatusReturnLevel)
-    }
-    pub fn registered_instruction(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::RegisteredInstruction as usize }>>::Ty {
-        self.to_data(ControlTable::RegisteredInstruction)
-    }
-    pub fn hardware_error_status(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::HardwareErrorStatus as usize }>>::Ty {
-        self.to_data(ControlTable::HardwareErrorStatus)
-    }
-    pub fn velocity_igain(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::VelocityIGain as usize }>>::Ty {
-        self.to_data(ControlTable::VelocityIGain)
-    }
-    pub fn velocity_pgain(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::VelocityPgain as usize }>>::Ty {
-        self.to_data(ControlTable::VelocityPgain)
-    }
-    pub fn position_dgain(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::PositionDGain as usize }>>::Ty {
-        self.to_data(ControlTable::PositionDGain)
-    }
-    pub fn position_igain(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::PositionIGain as usize }>>::Ty {
-        self.to_data(ControlTable::PositionIGain)
-    }
-    pub fn position_pgain(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::PositionPGain as usize }>>::Ty {
-        self.to_data(ControlTable::PositionPGain)
-    }
-    pub fn feedforward2nd_gain(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::Feedforward2ndGain as usize }>>::Ty {
-        self.to_data(ControlTable::Feedforward2ndGain)
-    }
-    pub fn feedforward1st_gain(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::Feedforward1stGain as usize }>>::Ty {
-        self.to_data(ControlTable::Feedforward1stGain)
-    }
-    pub fn bus_watchdog(&self) -> <() as CustomInt<{ ControlTable::BusWatchdog as usize }>>::Ty {
-        self.to_data(ControlTable::BusWatchdog)
-    }
-    pub fn goal_pwm(&self) -> <() as CustomInt<{ ControlTable::GoalPWM as usize }>>::Ty {
-        self.to_data(ControlTable::GoalPWM)
-    }
-    pub fn goal_current(&self) -> <() as CustomInt<{ ControlTable::GoalCurrent as usize }>>::Ty {
-        self.to_data(ControlTable::GoalCurrent)
-    }
-    pub fn goal_velocity(&self) -> <() as CustomInt<{ ControlTable::GoalVelocity as usize }>>::Ty {
-        self.to_data(ControlTable::GoalVelocity)
-    }
-    pub fn profile_accleration(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::ProfileAccleration as usize }>>::Ty {
-        self.to_data(ControlTable::ProfileAccleration)
-    }
-    pub fn profile_velocity(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::ProfileVelocity as usize }>>::Ty {
-        self.to_data(ControlTable::ProfileVelocity)
-    }
-    pub fn goal_position(&self) -> <() as CustomInt<{ ControlTable::GoalPosition as usize }>>::Ty {
-        self.to_data(ControlTable::GoalPosition)
-    }
-    pub fn realtime_tick(&self) -> <() as CustomInt<{ ControlTable::RealtimeTick as usize }>>::Ty {
-        self.to_data(ControlTable::RealtimeTick)
-    }
-    pub fn moving(&self) -> <() as CustomInt<{ ControlTable::Moving as usize }>>::Ty {
-        self.to_data(ControlTable::Moving)
-    }
-    pub fn moving_status(&self) -> <() as CustomInt<{ ControlTable::MovingStatus as usize }>>::Ty {
-        self.to_data(ControlTable::MovingStatus)
-    }
-    pub fn present_pwm(&self) -> <() as CustomInt<{ ControlTable::PresentPWM as usize }>>::Ty {
-        self.to_data(ControlTable::PresentPWM)
-    }
-    pub fn present_current(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::PresentCurrent as usize }>>::Ty {
-        self.to_data(ControlTable::PresentCurrent)
-    }
-    pub fn present_velocity(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::PresentVelocity as usize }>>::Ty {
-        self.to_data(ControlTable::PresentVelocity)
-    }
-    pub fn present_position(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::PresentPosition as usize }>>::Ty {
-        self.to_data(ControlTable::PresentPosition)
-    }
-    pub fn velocity_trajectory(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::VelocityTrajectory as usize }>>::Ty {
-        self.to_data(ControlTable::VelocityTrajectory)
-    }
-    pub fn position_trajectory(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::PositionTrajectory as usize }>>::Ty {
-        self.to_data(ControlTable::PositionTrajectory)
-    }
-    pub fn present_input_voltage(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::PresentInputVoltage as usize }>>::Ty {
-        self.to_data(ControlTable::PresentInputVoltage)
-    }
-    pub fn present_temperature(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::PresentTemperature as usize }>>::Ty {
-        self.to_data(ControlTable::PresentTemperature)
-    }
-    pub fn backup_ready(&self) -> <() as CustomInt<{ ControlTable::BackupReady as usize }>>::Ty {
-        self.to_data(ControlTable::BackupReady)
-    }
-    pub fn indirect_address1(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectAddress1 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectAddress1)
-    }
-    pub fn indirect_address2(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectAddress2 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectAddress2)
-    }
-    pub fn indirect_address3(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectAddress3 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectAddress3)
-    }
-    pub fn indirect_address4(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectAddress4 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectAddress4)
-    }
-    pub fn indirect_address5(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectAddress5 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectAddress5)
-    }
-    pub fn indirect_address6(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectAddress6 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectAddress6)
-    }
-    pub fn indirect_address7(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectAddress7 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectAddress7)
-    }
-    pub fn indirect_address8(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectAddress8 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectAddress8)
-    }
-    pub fn indirect_address9(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectAddress9 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectAddress9)
-    }
-    pub fn indirect_address10(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectAddress10 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectAddress10)
-    }
-    pub fn indirect_address11(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectAddress11 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectAddress11)
-    }
-    pub fn indirect_address12(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectAddress12 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectAddress12)
-    }
-    pub fn indirect_address13(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectAddress13 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectAddress13)
-    }
-    pub fn indirect_address14(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectAddress14 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectAddress14)
-    }
-    pub fn indirect_address15(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectAddress15 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectAddress15)
-    }
-    pub fn indirect_address16(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectAddress16 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectAddress16)
-    }
-    pub fn indirect_address17(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectAddress17 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectAddress17)
-    }
-    pub fn indirect_address18(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectAddress18 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectAddress18)
-    }
-    pub fn indirect_address19(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectAddress19 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectAddress19)
-    }
-    pub fn indirect_address20(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectAddress1 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectAddress1)
-    }
-    pub fn indirect_data1(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectData1 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectData1)
-    }
-    pub fn indirect_data2(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectData2 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectData2)
-    }
-    pub fn indirect_data3(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectData3 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectData3)
-    }
-    pub fn indirect_data4(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectData4 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectData4)
-    }
-    pub fn indirect_data5(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectData5 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectData5)
-    }
-    pub fn indirect_data6(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectData6 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectData6)
-    }
-    pub fn indirect_data7(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectData7 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectData7)
-    }
-    pub fn indirect_data8(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectData8 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectData8)
-    }
-    pub fn indirect_data9(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectData9 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectData9)
-    }
-    pub fn indirect_data10(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectData10 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectData10)
-    }
-    pub fn indirect_data11(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectData11 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectData11)
-    }
-    pub fn indirect_data12(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectData12 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectData12)
-    }
-    pub fn indirect_data13(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectData13 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectData13)
-    }
-    pub fn indirect_data14(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectData14 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectData14)
-    }
-    pub fn indirect_data15(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectData15 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectData15)
-    }
-    pub fn indirect_data16(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectData16 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectData16)
-    }
-    pub fn indirect_data17(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectData17 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectData17)
-    }
-    pub fn indirect_data18(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectData18 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectData18)
-    }
-    pub fn indirect_data19(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectData19 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectData19)
-    }
-    pub fn indirect_data20(
-        &self,
-    ) -> <() as CustomInt<{ ControlTable::IndirectData20 as usize }>>::Ty {
-        self.to_data(ControlTable::IndirectData20)
+    /// Value of the register regardless of the type, 0 if the model does not have it.
+    pub fn value(&self, ct: ControlTable) -> i64 {
+        match L::register(ct) {
+            Some(spec) => spec
+                .data_type
+                .from_le_bytes(&self.bits.as_ref()[spec.address as usize..]),
+            None => 0,
+        }
     }
 }
 
 /// Register writer.
 ///
 /// Used as an argument to the closures in the `write` and `modify` methods of the register.
-pub struct W {
+pub struct W<L: ControlTableLayout = XC330> {
     ///Writable bits
-    bits: Ux,
+    bits: L::Image,
 }
 
-impl W {
+impl<L: ControlTableLayout> W<L> {
     /// Writes raw bits to the register.
     #[inline(always)]
-    pub fn bits(&mut self, bits: Ux) -> &mut Self {
+    pub fn bits(&mut self, bits: L::Image) -> &mut Self {
         self.bits = bits;
         self
     }
     pub fn bytes(&mut self, address: usize, bytes: &[u8]) -> &mut Self {
+        let bits = self.bits.as_mut();
         for i in 0..bytes.len() {
-            if address + i >= self.bits.len() {
+            if address + i >= bits.len() {
                 break;
             }
-            self.bits[address + i] = bytes[i].clone();
+            bits[address + i] = bytes[i].clone();
         }
         self
     }
-    pub fn model_number(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::ModelNumber as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::ModelNumber,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn model_information(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::ModelInformation as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::ModelInformation,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn firmware_version(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::FirmwareVersion as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::FirmwareVersion,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn id(&mut self) -> BaseW<<() as CustomInt<{ ControlTable::ID as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::ID,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn baud_rate(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::BaudRate as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::BaudRate,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn return_delay_time(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::ReturnDelayTime as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::ReturnDelayTime,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn drive_mode(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::DriveMode as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::DriveMode,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn operating_mode(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::OperatingMode as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::OperatingMode,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn secondary_id(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::SecondaryID as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::SecondaryID,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn protocol_type(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::ProtocolType as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::ProtocolType,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn homing_offset(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::HomingOffset as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::HomingOffset,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn moving_threshold(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::MovingThreshold as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::MovingThreshold,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn temperature_limit(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::TemperatureLimit as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::TemperatureLimit,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn max_voltage_limit(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::MaxVoltageLimit as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::MaxVoltageLimit,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn min_voltage_limit(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::MinVoltageLimit as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::MinVoltageLimit,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn pwm_limit(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::PWMLimit as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::PWMLimit,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn current_limit(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::CurrentLimit as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::CurrentLimit,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn velocity_limit(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::VelocityLimit as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::VelocityLimit,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn max_position_limit(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::MaxPositionLimit as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::MaxPositionLimit,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn min_position_limit(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::MinPositionLimit as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::MinPositionLimit,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn startup_configuration(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::StartupConfiguration as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::StartupConfiguration,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn pwm_slope(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::PWMSlope as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::PWMSlope,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn shutdown(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::Shutdown as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::Shutdown,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn torque_enable(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::TorqueEnable as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::TorqueEnable,
-            _type: marker::PhantomData,
-        }
-    }
-
-    pub fn led(&mut self) -> BaseW<<() as CustomInt<{ ControlTable::LED as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::LED,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn status_return_level(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::StatusReturnLevel as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::StatusReturnLevel,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn registered_instruction(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::RegisteredInstruction as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::RegisteredInstruction,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn hardware_error_status(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::HardwareErrorStatus as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::HardwareErrorStatus,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn velocity_igain(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::VelocityIGain as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::VelocityIGain,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn velocity_pgain(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::VelocityPgain as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::VelocityPgain,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn position_dgain(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::PositionDGain as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::PositionDGain,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn position_igain(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::PositionIGain as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::PositionIGain,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn position_pgain(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::PositionPGain as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::PositionPGain,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn feedforward2nd_gain(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::Feedforward2ndGain as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::Feedforward2ndGain,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn feedforward1st_gain(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::Feedforward1stGain as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::Feedforward1stGain,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn bus_watchdog(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::BusWatchdog as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::BusWatchdog,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn goal_pwm(&mut self) -> BaseW<<() as CustomInt<{ ControlTable::GoalPWM as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::GoalPWM,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn goal_current(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::GoalCurrent as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::GoalCurrent,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn goal_velocity(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::GoalVelocity as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::GoalVelocity,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn profile_accleration(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::ProfileAccleration as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::ProfileAccleration,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn profile_velocity(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::ProfileVelocity as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::ProfileVelocity,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn goal_position(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::GoalPosition as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::GoalPosition,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn realtime_tick(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::RealtimeTick as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::RealtimeTick,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn moving(&mut self) -> BaseW<<() as CustomInt<{ ControlTable::Moving as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::Moving,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn moving_status(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::MovingStatus as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::MovingStatus,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn present_pwm(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::PresentPWM as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::PresentPWM,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn present_current(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::PresentCurrent as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::PresentCurrent,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn present_velocity(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::PresentVelocity as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::PresentVelocity,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn present_position(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::PresentPosition as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::PresentPosition,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn velocity_trajectory(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::VelocityTrajectory as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::VelocityTrajectory,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn position_trajectory(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::PositionTrajectory as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::PositionTrajectory,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn present_input_voltage(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::PresentInputVoltage as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::PresentInputVoltage,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn present_temperature(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::PresentTemperature as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::PresentTemperature,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn backup_ready(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::BackupReady as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::BackupReady,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_address1(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectAddress1 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectAddress1,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_address2(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectAddress2 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectAddress2,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_address3(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectAddress3 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectAddress3,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_address4(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectAddress4 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectAddress4,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_address5(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectAddress5 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectAddress5,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_address6(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectAddress6 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectAddress6,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_address7(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectAddress7 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectAddress7,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_address8(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectAddress8 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectAddress8,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_address9(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectAddress9 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectAddress9,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_address10(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectAddress10 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectAddress10,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_address11(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectAddress11 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectAddress11,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_address12(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectAddress12 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectAddress12,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_address13(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectAddress13 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectAddress13,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_address14(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectAddress14 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectAddress14,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_address15(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectAddress15 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectAddress15,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_address16(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectAddress16 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectAddress16,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_address17(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectAddress17 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectAddress17,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_address18(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectAddress18 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectAddress18,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_address19(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectAddress19 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectAddress19,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_address20(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectAddress20 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectAddress20,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_data1(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectData1 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectData1,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_data2(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectData2 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectData2,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_data3(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectData3 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectData3,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_data4(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectData4 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectData4,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_data5(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectData5 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectData5,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_data6(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectData6 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectData6,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_data7(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectData7 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectData7,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_data8(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectData8 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectData8,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_data9(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectData9 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectData9,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_data10(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectData10 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectData10,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_data11(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectData11 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectData11,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_data12(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectData12 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectData12,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_data13(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectData13 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectData13,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_data14(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectData14 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectData14,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_data15(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectData15 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectData15,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_data16(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectData16 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectData16,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_data17(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectData17 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectData17,
-            _type: marker::PhantomData,
+    /// Writes `value` to the register regardless of the type. Upper bytes are truncated.
+    pub fn value(&mut self, ct: ControlTable, value: i64) -> &mut Self {
+        if let Some(spec) = L::register(ct) {
+            let value = value.to_le_bytes();
+            self.bytes(spec.address as usize, &value[..spec.size as usize]);
         }
+        self
     }
-    pub fn indirect_data18(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectData18 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectData18,
-            _type: marker::PhantomData,
-        }
+    /// Writes the factory default value of the model to the register.
+    pub fn reset(&mut self, ct: ControlTable) -> &mut Self {
+        self.value(ct, L::default_value(ct))
     }
-    pub fn indirect_data19(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectData19 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectData19,
-            _type: marker::PhantomData,
-        }
-    }
-    pub fn indirect_data20(
-        &mut self,
-    ) -> BaseW<<() as CustomInt<{ ControlTable::IndirectData20 as usize }>>::Ty> {
-        BaseW {
-            w: self,
-            ct: ControlTable::IndirectData20,
-            _type: marker::PhantomData,
+    /// Writes the factory default values of the model to all registers.
+    pub fn defaults(&mut self) -> &mut Self {
+        for ct in ControlTable::ALL {
+            self.reset(*ct);
         }
+        self
     }
 }
 
-pub struct BaseW<'a, T> {
-    w: &'a mut W,
+pub struct BaseW<'a, T, L: ControlTableLayout = XC330> {
+    w: &'a mut W<L>,
     ct: ControlTable,
     _type: marker::PhantomData<T>,
 }
 
-pub trait BitsW<'a, P> {
-    fn bits(self, value: P) -> &'a mut W;
+impl<'a, T, L: ControlTableLayout> BaseW<'a, T, L> {
+    // 機種に無いレジスタへの書き込みは無視する
+    fn set_bytes(self, bytes: &[u8]) -> &'a mut W<L> {
+        if let Some(spec) = L::register(self.ct) {
+            let address = spec.address as usize;
+            self.w.bits.as_mut()[address..address + bytes.len()].copy_from_slice(bytes);
+        }
+        self.w
+    }
+}
+
+pub trait BitsW<'a, P, L: ControlTableLayout = XC330> {
+    fn bits(self, value: P) -> &'a mut W<L>;
 }
 
-impl<'a> BitsW<'a, u8> for BaseW<'a, u8> {
+impl<'a, L: ControlTableLayout> BitsW<'a, u8, L> for BaseW<'a, u8, L> {
     #[inline(always)]
-    fn bits(self, value: u8) -> &'a mut W {
-        self.w.bits[self.ct.to_address() as usize] = value;
-        self.w
+    fn bits(self, value: u8) -> &'a mut W<L> {
+        self.set_bytes(&value.to_le_bytes())
     }
 }
 
-impl<'a> BitsW<'a, u16> for BaseW<'a, u16> {
+impl<'a, L: ControlTableLayout> BitsW<'a, u16, L> for BaseW<'a, u16, L> {
     #[inline(always)]
-    fn bits(self, value: u16) -> &'a mut W {
-        let v = value.to_le_bytes();
-        self.w.bits[self.ct.to_address() as usize] = v[0];
-        self.w.bits[self.ct.to_address() as usize + 1] = v[1];
-        self.w
+    fn bits(self, value: u16) -> &'a mut W<L> {
+        self.set_bytes(&value.to_le_bytes())
     }
 }
 
-impl<'a> BitsW<'a, i16> for BaseW<'a, i16> {
+impl<'a, L: ControlTableLayout> BitsW<'a, i16, L> for BaseW<'a, i16, L> {
     #[inline(always)]
-    fn bits(self, value: i16) -> &'a mut W {
-        let v = value.to_le_bytes();
-        self.w.bits[self.ct.to_address() as usize] = v[0];
-        self.w.bits[self.ct.to_address() as usize + 1] = v[1];
-        self.w
+    fn bits(self, value: i16) -> &'a mut W<L> {
+        self.set_bytes(&value.to_le_bytes())
     }
 }
 
-impl<'a> BitsW<'a, u32> for BaseW<'a, u32> {
+impl<'a, L: ControlTableLayout> BitsW<'a, u32, L> for BaseW<'a, u32, L> {
     #[inline(always)]
-    fn bits(self, value: u32) -> &'a mut W {
-        let v = value.to_le_bytes();
-        self.w.bits[self.ct.to_address() as usize] = v[0];
-        self.w.bits[self.ct.to_address() as usize + 1] = v[1];
-        self.w.bits[self.ct.to_address() as usize + 2] = v[2];
-        self.w.bits[self.ct.to_address() as usize + 3] = v[3];
-        self.w
+    fn bits(self, value: u32) -> &'a mut W<L> {
+        self.set_bytes(&value.to_le_bytes())
     }
 }
 
-impl<'a> BitsW<'a, i32> for BaseW<'a, i32> {
+impl<'a, L: ControlTableLayout> BitsW<'a, i32, L> for BaseW<'a, i32, L> {
     #[inline(always)]
-    fn bits(self, value: i32) -> &'a mut W {
-        let v = value.to_le_bytes();
-        self.w.bits[self.ct.to_address() as usize] = v[0];
-        self.w.bits[self.ct.to_address() as usize + 1] = v[1];
-        self.w.bits[self.ct.to_address() as usize + 2] = v[2];
-        self.w.bits[self.ct.to_address() as usize + 3] = v[3];
-        self.w
+    fn bits(self, value: i32) -> &'a mut W<L> {
+        self.set_bytes(&value.to_le_bytes())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::control_table::CustomInt;
-    use crate::control_table::CONTROL_TABLE_SIZE;
-    use crate::control_table::{BitsW, ControlTable, ControlTableData, W};
+    use crate::control_table::{
+        BitsW, ControlTable, ControlTableAccess, ControlTableData, RegisterSet,
+        SharedControlTableData, W,
+    };
+    use crate::layout::{DataType, XC330, XM430};
+
+    const CONTROL_TABLE_SIZE: usize = 231;
 
     #[test]
     fn to_address() {
@@ -2063,17 +876,35 @@ mod tests {
         assert_eq!(name.to_size(), 2);
         assert_eq!(ControlTable::ModelInformation.to_size(), 4)
     }
-    // macro_rules! size_of {
-    //     ($input:expr) => {
-    //         core::mem::size_of::<<() as CustomInt<{ $input as usize }>>::Ty>() as u16
-    //     };
-    // }
+    #[test]
+    fn to_size_and_type() {
+        assert_eq!(ControlTable::VelocityLimit.to_size(), 4);
+        assert_eq!(ControlTable::VelocityLimit.data_type(), DataType::U32);
+        let mut end = 0;
+        for ct in ControlTable::ALL {
+            let size = match ct.data_type() {
+                DataType::U8 => 1,
+                DataType::U16 | DataType::I16 => 2,
+                DataType::U32 | DataType::I32 => 4,
+            };
+            assert_eq!(ct.to_size(), size);
+            // アドレス順に重ならずに並んでいる
+            assert!(ct.to_address() >= end, "{:?}", ct);
+            end = ct.to_address() + ct.to_size();
+            assert!(ct.range().contains(&ct.default_value()), "{:?}", ct);
+        }
+        assert_eq!(end, 228);
+    }
 
-    // #[test]
-    // fn to_size_and_type() {
-    //     const NAME: ControlTable = ControlTable::ModelNumber;
-    //     assert_eq!(NAME.to_size(), size_of!(NAME));
-    // }
+    #[test]
+    fn accessor() {
+        let ctd = ControlTableData::new();
+        ctd.write(|w| w.velocity_limit().bits(0x0001_0000));
+        assert_eq!(ctd.read().velocity_limit(), 0x0001_0000);
+        ctd.write(|w| w.indirect_address20().bits(0x1234));
+        assert_eq!(ctd.read().indirect_address1(), 0);
+        assert_eq!(ctd.read().indirect_address20(), 0x1234);
+    }
 
     #[test]
     fn read() {
@@ -2120,4 +951,125 @@ mod tests {
         let ctd = ControlTableData::new();
         ctd.write(|w| w.indirect_address1().bits(0x2222));
     }
+
+    #[test]
+    fn layout() {
+        let ctd = ControlTableData::<XM430>::with_layout();
+        assert_eq!(ctd.read().bits().len(), 662);
+        ctd.write(|w| w.indirect_data1().bits(0x12));
+        assert_eq!(ctd.read().bits()[224], 0x12);
+        assert_eq!(ctd.read().indirect_data1(), 0x12);
+        // XM430にPWM Slopeは無い
+        ctd.modify(|_, w| w.pwm_slope().bits(0x34));
+        assert_eq!(ctd.read().pwm_slope(), 0);
+        assert_eq!(ctd.read().bits()[62], 0);
+        ctd.modify(|_, w| w.bytes(661, &[1, 2]));
+        assert_eq!(ctd.read().bits()[661], 1);
+    }
+
+    #[test]
+    fn shared() {
+        static CTD: SharedControlTableData = SharedControlTableData::new();
+        CTD.write(|w| w.defaults());
+        assert_eq!(CTD.read().model_number(), 1240);
+        assert_eq!(CTD.get(ControlTable::ReturnDelayTime), 250);
+
+        CTD.set(ControlTable::GoalPosition, -5);
+        assert_eq!(CTD.get(ControlTable::GoalPosition), -5);
+        assert_eq!(CTD.read().goal_position(), -5);
+        CTD.modify(|_, w| w.goal_current().bits(-100));
+        assert_eq!(CTD.get(ControlTable::GoalCurrent), -100);
+        CTD.set(ControlTable::ModelInformation, 0xFFFF_FFFF);
+        assert_eq!(CTD.get(ControlTable::ModelInformation), 0xFFFF_FFFF);
+
+        // 機種に無いレジスタ
+        let ctd = SharedControlTableData::from(ControlTableData::with_defaults::<XM430>());
+        assert_eq!(ctd.get(ControlTable::ModelNumber), 1020);
+        ctd.set(ControlTable::PWMSlope, 1);
+        assert_eq!(ctd.get(ControlTable::PWMSlope), 0);
+        assert_eq!(ctd.read().bits()[62], 0);
+    }
+
+    #[test]
+    fn snapshot() {
+        let ctd = ControlTableData::new();
+        let mut snapshot = ctd.snapshot();
+        snapshot.write().present_position().bits(100);
+        snapshot.write().present_velocity().bits(-3);
+        assert_eq!(snapshot.read().present_position(), 100);
+        // commitするまでは見えない
+        assert_eq!(ctd.read().present_position(), 0);
+        // snapshotの後に受信した書き込み
+        ctd.modify(|_, w| w.goal_position().bits(50));
+        assert_eq!(snapshot.read().goal_position(), 0);
+
+        ctd.commit(&snapshot);
+        assert_eq!(ctd.read().present_position(), 100);
+        assert_eq!(ctd.read().present_velocity(), -3);
+        assert_eq!(ctd.read().goal_position(), 50);
+
+        static CTD: SharedControlTableData = SharedControlTableData::new();
+        let mut snapshot = CTD.snapshot();
+        snapshot.write().present_current().bits(-20);
+        CTD.set(ControlTable::TorqueEnable, 1);
+        CTD.commit(&snapshot);
+        assert_eq!(CTD.get(ControlTable::PresentCurrent), -20);
+        assert_eq!(CTD.get(ControlTable::TorqueEnable), 1);
+
+        // 両方で変更されたレジスタはsnapshotの値になる
+        let ctd = ControlTableData::new();
+        ctd.modify(|_, w| w.goal_position().bits(0x0102));
+        let mut snapshot = ctd.snapshot();
+        snapshot.write().goal_position().bits(0x0103);
+        ctd.modify(|_, w| w.goal_position().bits(0x0502));
+        ctd.commit(&snapshot);
+        assert_eq!(ctd.read().goal_position(), 0x0103);
+    }
+
+    #[test]
+    fn register_set() {
+        let mut set = RegisterSet::new();
+        assert!(set.is_empty());
+        set.insert(ControlTable::IndirectData20);
+        set.insert(ControlTable::ModelNumber);
+        assert!(set.contains(ControlTable::IndirectData20));
+        assert!(!set.contains(ControlTable::ID));
+        set.remove(ControlTable::ModelNumber);
+        assert_eq!(set.iter().count(), 1);
+        set.clear();
+        assert!(set.is_empty());
+
+        // Goal Velocityの後半からGoal Positionの前半まで
+        set.insert_range::<XC330>(106, 12);
+        let mut iter = set.iter();
+        assert_eq!(iter.next(), Some(ControlTable::GoalVelocity));
+        assert_eq!(iter.next(), Some(ControlTable::ProfileAccleration));
+        assert_eq!(iter.next(), Some(ControlTable::ProfileVelocity));
+        assert_eq!(iter.next(), Some(ControlTable::GoalPosition));
+        assert_eq!(iter.next(), None);
+
+        let mut set = RegisterSet::new();
+        set.insert_range::<XM430>(224, 1);
+        assert_eq!(set.iter().next(), Some(ControlTable::IndirectData1));
+    }
+
+    #[test]
+    fn with_defaults() {
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        assert_eq!(ctd.read().model_number(), 1240);
+        assert_eq!(ctd.read().id(), 1);
+        assert_eq!(ctd.read().baud_rate(), 1);
+        assert_eq!(ctd.read().return_delay_time(), 250);
+        assert_eq!(ctd.read().max_position_limit(), 4095);
+        assert_eq!(ctd.read().indirect_address1(), 208);
+        assert_eq!(ctd.read().goal_position(), 0);
+
+        let ctd = ControlTableData::with_defaults::<XM430>();
+        assert_eq!(ctd.read().model_number(), 1020);
+        assert_eq!(ctd.read().temperature_limit(), 80);
+        assert_eq!(ctd.read().position_pgain(), 800);
+        assert_eq!(ctd.read().indirect_address1(), 224);
+        // XM430にPWM Slopeは無い
+        assert_eq!(ctd.read().bits()[62], 0);
+    }
 }
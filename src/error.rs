@@ -0,0 +1,49 @@
+use crate::CommunicationResult;
+
+use core::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    Communication(CommunicationResult),
+    BufferFull,
+    InvalidLength,
+    Unsupported,
+    /// Access to `NvStorage` failed.
+    Storage,
+}
+
+impl From<CommunicationResult> for Error {
+    fn from(result: CommunicationResult) -> Self {
+        Error::Communication(result)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Communication(ref result) => result.fmt(f),
+            Error::BufferFull => write!(f, "[Error] Buffer is full!"),
+            Error::InvalidLength => write!(f, "[Error] Invalid packet or data length!"),
+            Error::Unsupported => write!(f, "[Error] Instruction is not supported!"),
+            Error::Storage => write!(f, "[Error] Non-volatile storage access failed!"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::CommunicationResult;
+    use crate::Error;
+    use core::fmt::Write;
+    use heapless::String;
+
+    #[test]
+    fn display() {
+        let mut s = String::<64>::new();
+        write!(s, "{}", Error::from(CommunicationResult::RxCRCError)).unwrap();
+        assert_eq!(s, "[TxRxResult] Incorrect Rx CRC!");
+        s.clear();
+        write!(s, "{}", Error::BufferFull).unwrap();
+        assert_eq!(s, "[Error] Buffer is full!");
+    }
+}
@@ -0,0 +1,149 @@
+//! `HardwareErrorStatus` and the shutdown behavior.
+//!
+//! Input voltage and temperature are checked against the limits in the control table. Other
+//! faults can only be detected by the application and are passed to `HardwareErrorMonitor::report`.
+//! The errors are kept until `clear` is called, e.g. on reboot. While an error enabled in
+//! `Shutdown` is set, the torque is kept off.
+use crate::control_table::{BitsW, ControlTableAccess};
+
+/// Bits of `HardwareErrorStatus` and `Shutdown`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HardwareError {
+    InputVoltage,
+    Overheating,
+    MotorEncoder,
+    ElectricalShock,
+    Overload,
+}
+
+impl HardwareError {
+    pub fn to_bit(&self) -> u8 {
+        match self {
+            HardwareError::InputVoltage => 0x01,
+            HardwareError::Overheating => 0x04,
+            HardwareError::MotorEncoder => 0x08,
+            HardwareError::ElectricalShock => 0x10,
+            HardwareError::Overload => 0x20,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct HardwareErrorMonitor {
+    // 次のupdateで反映する
+    reported: u8,
+}
+
+impl HardwareErrorMonitor {
+    pub fn new() -> Self {
+        Self { reported: 0 }
+    }
+
+    /// Report a fault detected by the application.
+    pub fn report(&mut self, error: HardwareError) {
+        self.reported |= error.to_bit();
+    }
+
+    /// Check the present values and update `HardwareErrorStatus`. Call this every control tick
+    /// after the present values are written and before the actuator is driven.
+    ///
+    /// Returns true if the torque is shut down.
+    pub fn update<D: ControlTableAccess>(&mut self, ctd: &D) -> bool {
+        let reported = core::mem::take(&mut self.reported);
+        let mut shutdown = false;
+        ctd.modify(|r, w| {
+            let mut status = r.hardware_error_status() | reported;
+            let voltage = r.present_input_voltage();
+            if voltage > r.max_voltage_limit() || voltage < r.min_voltage_limit() {
+                status |= HardwareError::InputVoltage.to_bit();
+            }
+            if r.present_temperature() > r.temperature_limit() {
+                status |= HardwareError::Overheating.to_bit();
+            }
+            w.hardware_error_status().bits(status);
+            shutdown = status & r.shutdown() != 0;
+            if shutdown {
+                w.torque_enable().bits(0);
+            }
+            w
+        });
+        shutdown
+    }
+
+    /// Clear all errors.
+    pub fn clear<D: ControlTableAccess>(&mut self, ctd: &D) {
+        self.reported = 0;
+        ctd.modify(|_, w| w.hardware_error_status().bits(0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::control_table::BitsW;
+    use crate::hardware_error::{HardwareError, HardwareErrorMonitor};
+    use crate::{ControlTableData, XC330};
+
+    #[test]
+    fn limits() {
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        ctd.modify(|_, w| {
+            w.present_input_voltage()
+                .bits(50)
+                .present_temperature()
+                .bits(40)
+                .torque_enable()
+                .bits(1)
+        });
+        let mut monitor = HardwareErrorMonitor::new();
+        assert!(!monitor.update(&ctd));
+        assert_eq!(ctd.read().hardware_error_status(), 0);
+        assert_eq!(ctd.read().torque_enable(), 1);
+
+        ctd.modify(|_, w| w.present_temperature().bits(71));
+        assert!(monitor.update(&ctd));
+        assert_eq!(ctd.read().hardware_error_status(), 0x04);
+        assert_eq!(ctd.read().torque_enable(), 0);
+
+        // 温度が下がってもクリアするまで残る
+        ctd.modify(|_, w| {
+            w.present_temperature()
+                .bits(40)
+                .present_input_voltage()
+                .bits(30)
+                .torque_enable()
+                .bits(1)
+        });
+        assert!(monitor.update(&ctd));
+        assert_eq!(ctd.read().hardware_error_status(), 0x05);
+        assert_eq!(ctd.read().torque_enable(), 0);
+
+        ctd.modify(|_, w| w.present_input_voltage().bits(50));
+        monitor.clear(&ctd);
+        assert!(!monitor.update(&ctd));
+        assert_eq!(ctd.read().hardware_error_status(), 0);
+    }
+
+    #[test]
+    fn report() {
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        ctd.modify(|_, w| {
+            w.present_input_voltage()
+                .bits(50)
+                .present_temperature()
+                .bits(40)
+                .torque_enable()
+                .bits(1)
+        });
+        let mut monitor = HardwareErrorMonitor::new();
+        // 初期値のShutdownにはMotor Encoderが含まれない
+        monitor.report(HardwareError::MotorEncoder);
+        assert!(!monitor.update(&ctd));
+        assert_eq!(ctd.read().hardware_error_status(), 0x08);
+        assert_eq!(ctd.read().torque_enable(), 1);
+
+        monitor.report(HardwareError::Overload);
+        assert!(monitor.update(&ctd));
+        assert_eq!(ctd.read().hardware_error_status(), 0x28);
+        assert_eq!(ctd.read().torque_enable(), 0);
+    }
+}
@@ -21,6 +21,31 @@ pub enum Instruction {
     Unknown = 0xFF,
 }
 
+impl Instruction {
+    /// Unknown codes are mapped to `Instruction::Unknown`.
+    pub fn from_value(value: u8) -> Self {
+        match value {
+            0x01 => Instruction::Ping,
+            0x02 => Instruction::Read,
+            0x03 => Instruction::Write,
+            0x04 => Instruction::RegWrite,
+            0x05 => Instruction::Action,
+            0x06 => Instruction::FactoryReset,
+            0x08 => Instruction::Reboot,
+            0x10 => Instruction::Clear,
+            0x20 => Instruction::ControlTableBackup,
+            0x55 => Instruction::Status,
+            0x82 => Instruction::SyncRead,
+            0x83 => Instruction::SyncWrite,
+            0x8A => Instruction::FastSyncRead,
+            0x92 => Instruction::BulkRead,
+            0x93 => Instruction::BulkWrite,
+            0x9A => Instruction::FastBulkRead,
+            _ => Instruction::Unknown,
+        }
+    }
+}
+
 impl From<Instruction> for u8 {
     #[inline(always)]
     fn from(variant: Instruction) -> Self {
@@ -39,4 +64,10 @@ mod tests {
         let s: u8 = Instruction::Clear.into();
         assert_eq!(s, 0x10);
     }
+
+    #[test]
+    fn from_value() {
+        assert_eq!(Instruction::from_value(0x83), Instruction::SyncWrite);
+        assert_eq!(Instruction::from_value(0x07), Instruction::Unknown);
+    }
 }
@@ -0,0 +1,253 @@
+//! Control table layouts of the supported servo models.
+//!
+//! `ControlTableData` and `DynamixelProtocolHandler` are generic over `ControlTableLayout`, so the
+//! same firmware can answer as a different model by changing the type parameter.
+use crate::control_table::ControlTable;
+use core::ops::RangeInclusive;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    ReadOnly,
+    ReadWrite,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DataType {
+    U8,
+    U16,
+    I16,
+    U32,
+    I32,
+}
+
+impl DataType {
+    /// Value of the little endian `bytes` of the register.
+    pub fn from_le_bytes(&self, bytes: &[u8]) -> i64 {
+        match self {
+            DataType::U8 => bytes[0] as i64,
+            DataType::U16 => u16::from_le_bytes([bytes[0], bytes[1]]) as i64,
+            DataType::I16 => i16::from_le_bytes([bytes[0], bytes[1]]) as i64,
+            DataType::U32 => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as i64,
+            DataType::I32 => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as i64,
+        }
+    }
+}
+
+/// Placement of one register in the control table of a model.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RegisterSpec {
+    pub address: u16,
+    pub size: u16,
+    pub access: Access,
+    pub data_type: DataType,
+}
+
+pub trait ControlTableLayout {
+    /// Raw bytes of the whole control table.
+    type Image: Copy + AsRef<[u8]> + AsMut<[u8]>;
+    /// Image filled with zero.
+    const EMPTY: Self::Image;
+    /// Value of the Model Number register.
+    const MODEL_NUMBER: u16;
+    /// Position ticks per revolution.
+    const POSITION_RESOLUTION: u32 = 4096;
+    /// Velocity per unit [rev/min].
+    const VELOCITY_UNIT: f32 = 0.229;
+    /// Acceleration per unit of Profile Acceleration [rev/min^2].
+    const ACCELERATION_UNIT: f32 = 214.577;
+    /// Current per unit [mA].
+    const CURRENT_UNIT: f32 = 1.0;
+    /// `None` if the model does not have the register.
+    fn register(ct: ControlTable) -> Option<RegisterSpec>;
+
+    /// Factory default value of the register.
+    fn default_value(ct: ControlTable) -> i64 {
+        match ct {
+            ControlTable::ModelNumber => Self::MODEL_NUMBER as i64,
+            _ => ct.default_value(),
+        }
+    }
+
+    /// Range of the value that can be written to the register.
+    fn range(ct: ControlTable) -> RangeInclusive<i64> {
+        ct.range()
+    }
+
+    /// Size of the image, which is the largest address that can be read or written.
+    fn image_len() -> usize {
+        Self::EMPTY.as_ref().len()
+    }
+}
+
+// アドレスとサイズ以外は機種によらず共通
+fn spec(ct: ControlTable, address: u16) -> RegisterSpec {
+    RegisterSpec {
+        address,
+        size: ct.to_size(),
+        access: ct.access(),
+        data_type: ct.data_type(),
+    }
+}
+
+/// XC330-M288. `ControlTable::to_address` follows this layout.
+pub struct XC330;
+
+impl ControlTableLayout for XC330 {
+    // Dynamixel Wizardが大きめに読むのでアドレス終端の227よりも大きくする
+    type Image = [u8; 231];
+    const EMPTY: Self::Image = [0; 231];
+    const MODEL_NUMBER: u16 = 1240;
+    fn register(ct: ControlTable) -> Option<RegisterSpec> {
+        Some(spec(ct, ct.to_address()))
+    }
+}
+
+/// XL330-M288. The addresses are the same as XC330.
+pub struct XL330;
+
+impl ControlTableLayout for XL330 {
+    type Image = [u8; 231];
+    const EMPTY: Self::Image = [0; 231];
+    const MODEL_NUMBER: u16 = 1200;
+    fn register(ct: ControlTable) -> Option<RegisterSpec> {
+        XC330::register(ct)
+    }
+}
+
+/// XM430-W350.
+///
+/// There is no PWM Slope, and the indirect area is larger. Only Indirect Address/Data 1-20 are
+/// named by `ControlTable`, the rest can be accessed as raw bytes.
+pub struct XM430;
+
+impl ControlTableLayout for XM430 {
+    // Indirect Data 56(661)まで
+    type Image = [u8; 662];
+    const EMPTY: Self::Image = [0; 662];
+    const MODEL_NUMBER: u16 = 1020;
+    const CURRENT_UNIT: f32 = 2.69;
+    fn register(ct: ControlTable) -> Option<RegisterSpec> {
+        let index = ct as u16;
+        let address = if ct == ControlTable::PWMSlope {
+            return None;
+        } else if index >= ControlTable::IndirectData1 as u16 {
+            224 + (index - ControlTable::IndirectData1 as u16)
+        } else if index >= ControlTable::IndirectAddress1 as u16 {
+            168 + (index - ControlTable::IndirectAddress1 as u16) * 2
+        } else {
+            ct.to_address()
+        };
+        Some(spec(ct, address))
+    }
+    fn default_value(ct: ControlTable) -> i64 {
+        let index = ct as i64;
+        match ct {
+            ControlTable::ModelNumber => Self::MODEL_NUMBER as i64,
+            ControlTable::TemperatureLimit => 80,
+            ControlTable::MaxVoltageLimit => 160,
+            ControlTable::MinVoltageLimit => 95,
+            ControlTable::CurrentLimit => 1193,
+            ControlTable::VelocityLimit => 200,
+            ControlTable::VelocityIGain => 1920,
+            ControlTable::PositionPGain => 800,
+            ControlTable::Shutdown => 52,
+            // 初期状態では同じ番号のIndirect Dataを指す
+            _ if index >= ControlTable::IndirectAddress1 as i64
+                && index < ControlTable::IndirectData1 as i64 =>
+            {
+                224 + index - ControlTable::IndirectAddress1 as i64
+            }
+            _ => ct.default_value(),
+        }
+    }
+    fn range(ct: ControlTable) -> RangeInclusive<i64> {
+        let index = ct as i64;
+        match ct {
+            ControlTable::MaxVoltageLimit | ControlTable::MinVoltageLimit => 95..=160,
+            ControlTable::CurrentLimit => 0..=1193,
+            ControlTable::VelocityLimit => 0..=1023,
+            ControlTable::GoalCurrent => -1193..=1193,
+            ControlTable::GoalVelocity => -1023..=1023,
+            // Indirect Data 56(661)まで指せる
+            _ if index >= ControlTable::IndirectAddress1 as i64
+                && index < ControlTable::IndirectData1 as i64 =>
+            {
+                64..=661
+            }
+            _ => ct.range(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::control_table::ControlTable;
+    use crate::layout::{Access, ControlTableLayout, DataType, XC330, XL330, XM430};
+
+    #[test]
+    fn xc330() {
+        let spec = XC330::register(ControlTable::GoalPosition).unwrap();
+        assert_eq!(spec.address, 116);
+        assert_eq!(spec.size, 4);
+        assert_eq!(spec.access, Access::ReadWrite);
+        assert_eq!(spec.data_type, DataType::I32);
+        let spec = XC330::register(ControlTable::PresentPosition).unwrap();
+        assert_eq!(spec.address, 132);
+        assert_eq!(spec.access, Access::ReadOnly);
+        assert_eq!(
+            XC330::register(ControlTable::IndirectData1)
+                .unwrap()
+                .address,
+            208
+        );
+        assert_eq!(XC330::image_len(), 231);
+        assert_eq!(
+            XL330::register(ControlTable::PWMSlope),
+            XC330::register(ControlTable::PWMSlope)
+        );
+    }
+
+    #[test]
+    fn xm430() {
+        assert_eq!(XM430::register(ControlTable::PWMSlope), None);
+        let spec = XM430::register(ControlTable::Shutdown).unwrap();
+        assert_eq!(spec.address, 63);
+        assert_eq!(spec.access, Access::ReadWrite);
+        let spec = XM430::register(ControlTable::IndirectAddress20).unwrap();
+        assert_eq!(spec.address, 206);
+        assert_eq!(spec.size, 2);
+        let spec = XM430::register(ControlTable::IndirectData1).unwrap();
+        assert_eq!(spec.address, 224);
+        assert_eq!(spec.size, 1);
+        assert_eq!(XM430::image_len(), 662);
+    }
+
+    #[test]
+    fn default_value() {
+        assert_eq!(XC330::default_value(ControlTable::ModelNumber), 1240);
+        assert_eq!(XL330::default_value(ControlTable::ModelNumber), 1200);
+        assert_eq!(XL330::default_value(ControlTable::ReturnDelayTime), 250);
+        assert_eq!(XM430::default_value(ControlTable::ModelNumber), 1020);
+        assert_eq!(XM430::default_value(ControlTable::MaxVoltageLimit), 160);
+        assert_eq!(XM430::default_value(ControlTable::IndirectAddress1), 224);
+        assert_eq!(XM430::default_value(ControlTable::IndirectAddress20), 243);
+        assert_eq!(XC330::default_value(ControlTable::IndirectAddress20), 227);
+        assert_eq!(XM430::default_value(ControlTable::ID), 1);
+    }
+
+    fn defaults_in_range<L: ControlTableLayout>() {
+        for ct in ControlTable::ALL {
+            assert!(L::range(*ct).contains(&L::default_value(*ct)), "{:?}", ct);
+        }
+    }
+
+    #[test]
+    fn range() {
+        assert_eq!(XC330::range(ControlTable::MaxVoltageLimit), 31..=70);
+        assert_eq!(XM430::range(ControlTable::MaxVoltageLimit), 95..=160);
+        assert_eq!(XM430::range(ControlTable::IndirectAddress20), 64..=661);
+        defaults_in_range::<XC330>();
+        defaults_in_range::<XL330>();
+        defaults_in_range::<XM430>();
+    }
+}
@@ -1,24 +1,58 @@
-#![no_std]
+#![cfg_attr(not(feature = "std"), no_std)]
 //! This crate is for dynamixel protocol firmware.
 //! Use this crate to share same bus line with dynamixels and communicate.
 //!
 #![allow(unused_imports)]
 pub mod buffer;
+#[cfg(feature = "control")]
+pub mod control;
 pub mod control_data;
 pub mod control_table;
+pub mod crc;
 mod data_spec;
-mod instruction;
+pub mod error;
+pub mod hardware_error;
+pub mod instruction;
+pub mod layout;
+pub mod mode;
+pub mod moving;
+pub mod packet;
 pub mod packet_handler;
+pub mod parser;
+pub mod profile;
+#[cfg(feature = "std")]
+pub mod serial;
+#[cfg(feature = "sim")]
+pub mod sim;
+pub mod stats;
+pub mod storage;
+pub mod units;
 pub mod utils;
 
 pub use buffer::RingBuffer;
 pub use control_data::*;
 pub use control_table::ControlTable;
+pub use control_table::ControlTableAccess;
 pub use control_table::ControlTableData;
-use instruction::Instruction;
+pub use control_table::RegisterSet;
+pub use control_table::SharedControlTableData;
+pub use control_table::Snapshot;
+pub use crc::{Crc16, CrcCalculator};
+pub use error::Error;
+pub use hardware_error::{HardwareError, HardwareErrorMonitor};
+pub use instruction::Instruction;
+pub use layout::{ControlTableLayout, XC330, XL330, XM430};
+pub use mode::{ModeChange, ModeController};
+pub use moving::MovingDetector;
+pub use packet::{InstructionPacket, PacketBuilder, StatusPacket};
 pub use packet_handler::CommunicationResult;
 pub use packet_handler::DynamixelProtocolHandler;
+pub use packet_handler::PacketEvent;
 use packet_handler::MAX_PACKET_LEN;
+pub use parser::PacketParser;
+pub use profile::ProfileGenerator;
+pub use stats::CommunicationStats;
+pub use storage::{EepromPersistence, NvStorage};
 pub use utils::DegRad;
 
 use core::result::Result;
@@ -32,10 +66,12 @@ pub trait BufferInterface {
     // return read size as Option
     fn read_bytes(&mut self, buf: &mut [u8]) -> Option<usize>;
     fn clear_read_buf(&mut self);
+    // called after the status packet has been sent when the BaudRate register is changed
+    fn set_baudrate(&mut self, _baudrate: u32) {}
 }
 
 pub trait QueueInterface {
-    fn enqueue(&mut self, data: u8) -> Result<(), ()>;
+    fn enqueue(&mut self, data: u8) -> Result<(), Error>;
 }
 
 pub trait Clock {
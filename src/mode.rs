@@ -0,0 +1,146 @@
+//! Operating mode state machine of the controller.
+//!
+//! The host can write any value to the `OperatingMode` register. `ModeController` decides whether
+//! the written mode is accepted and keeps the mode the control loop is actually running.
+use crate::control_data::OperatingMode;
+use crate::control_table::{BitsW, ControlTable, ControlTableAccess};
+
+/// Result of `ModeController::update`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ModeChange {
+    Unchanged,
+    /// The mode was switched and the goal registers were reinitialized.
+    Switched(OperatingMode),
+    /// The register was restored because the torque is on or the value is not a valid mode.
+    Rejected,
+}
+
+pub struct ModeController {
+    mode: OperatingMode,
+}
+
+impl ModeController {
+    /// Start with the mode in the register. An invalid value is replaced by the position control
+    /// mode.
+    pub fn new<D: ControlTableAccess>(ctd: &D) -> Self {
+        let mode = OperatingMode::from_value(ctd.read().operating_mode())
+            .unwrap_or(OperatingMode::PositionControlMode);
+        ctd.modify(|_, w| w.operating_mode().bits(mode.to_value()));
+        Self { mode }
+    }
+
+    /// Mode the controller is running.
+    pub fn mode(&self) -> OperatingMode {
+        self.mode
+    }
+
+    /// Goal register the controller follows in the current mode.
+    pub fn goal_register(&self) -> ControlTable {
+        self.mode.goal_register()
+    }
+
+    /// Check the `OperatingMode` register. Call this every control tick.
+    pub fn update<D: ControlTableAccess>(&mut self, ctd: &D) -> ModeChange {
+        let mut change = ModeChange::Unchanged;
+        let current = self.mode;
+        ctd.modify(|r, w| {
+            let value = r.operating_mode();
+            if value == current.to_value() {
+                return w;
+            }
+            // トルクONの間はモードを変更できない
+            let mode = match OperatingMode::from_value(value) {
+                Some(mode) if r.torque_enable() == 0 => mode,
+                _ => {
+                    change = ModeChange::Rejected;
+                    return w.operating_mode().bits(current.to_value());
+                }
+            };
+            change = ModeChange::Switched(mode);
+            // 切り替え直後に動き出さないように目標値を初期化する
+            w.goal_pwm()
+                .bits(r.pwm_limit() as i16)
+                .goal_current()
+                .bits(r.current_limit() as i16)
+                .goal_velocity()
+                .bits(0)
+                .goal_position()
+                .bits(r.present_position())
+        });
+        if let ModeChange::Switched(mode) = change {
+            self.mode = mode;
+        }
+        change
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::control_data::OperatingMode;
+    use crate::control_table::{BitsW, ControlTable};
+    use crate::mode::{ModeChange, ModeController};
+    use crate::{ControlTableData, XC330};
+
+    #[test]
+    fn switch() {
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        let mut mode = ModeController::new(&ctd);
+        assert_eq!(mode.mode(), OperatingMode::PositionControlMode);
+        assert_eq!(mode.goal_register(), ControlTable::GoalPosition);
+        assert_eq!(mode.update(&ctd), ModeChange::Unchanged);
+
+        ctd.modify(|_, w| {
+            w.present_position()
+                .bits(1000)
+                .goal_position()
+                .bits(3000)
+                .goal_velocity()
+                .bits(100)
+                .operating_mode()
+                .bits(1)
+        });
+        assert_eq!(
+            mode.update(&ctd),
+            ModeChange::Switched(OperatingMode::VelocityControlMode)
+        );
+        assert_eq!(mode.mode(), OperatingMode::VelocityControlMode);
+        assert_eq!(mode.goal_register(), ControlTable::GoalVelocity);
+        let r = ctd.read();
+        assert_eq!(r.goal_position(), 1000);
+        assert_eq!(r.goal_velocity(), 0);
+        assert_eq!(r.goal_pwm(), 885);
+        assert_eq!(r.goal_current(), 1750);
+    }
+
+    #[test]
+    fn reject() {
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        let mut mode = ModeController::new(&ctd);
+
+        // トルクONの間は戻される
+        ctd.modify(|_, w| w.torque_enable().bits(1).operating_mode().bits(16));
+        assert_eq!(mode.update(&ctd), ModeChange::Rejected);
+        assert_eq!(mode.mode(), OperatingMode::PositionControlMode);
+        assert_eq!(ctd.read().operating_mode(), 3);
+
+        // 存在しないモード
+        ctd.modify(|_, w| w.torque_enable().bits(0).operating_mode().bits(2));
+        assert_eq!(mode.update(&ctd), ModeChange::Rejected);
+        assert_eq!(ctd.read().operating_mode(), 3);
+
+        ctd.modify(|_, w| w.operating_mode().bits(16));
+        assert_eq!(
+            mode.update(&ctd),
+            ModeChange::Switched(OperatingMode::PWMControMode)
+        );
+    }
+
+    #[test]
+    fn invalid_initial_mode() {
+        let ctd = ControlTableData::new();
+        ctd.modify(|_, w| w.operating_mode().bits(7));
+        let mode = ModeController::new(&ctd);
+        assert_eq!(mode.mode(), OperatingMode::PositionControlMode);
+        assert_eq!(ctd.read().operating_mode(), 3);
+    }
+}
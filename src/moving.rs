@@ -0,0 +1,130 @@
+//! `Moving` and `MovingStatus` registers.
+use crate::control_data::OperatingMode;
+use crate::control_table::{BitsW, ControlTableAccess};
+use crate::profile::ProfileGenerator;
+
+const IN_POSITION: u8 = 0x01;
+const PROFILE_ONGOING: u8 = 0x02;
+const FOLLOWING_ERROR: u8 = 0x08;
+
+pub struct MovingDetector {
+    in_position: u32,
+}
+
+impl MovingDetector {
+    /// `in_position` is the largest distance between `GoalPosition` and `PresentPosition` in ticks
+    /// regarded as reached.
+    pub fn new(in_position: u32) -> Self {
+        Self { in_position }
+    }
+
+    /// Update the registers. Call this every control tick after the present values are written.
+    pub fn update<D: ControlTableAccess>(
+        &self,
+        ctd: &D,
+        mode: OperatingMode,
+        profile: &ProfileGenerator,
+    ) {
+        let active = profile.is_active();
+        let in_position = self.in_position;
+        ctd.modify(|r, w| {
+            let mut status = profile.profile_type().to_value() << 4;
+            if active {
+                status |= PROFILE_ONGOING;
+            }
+            match mode {
+                OperatingMode::PositionControlMode
+                | OperatingMode::ExtendedPosionControlMode
+                | OperatingMode::CurrentBasedPositionControlMode => {
+                    let error = r.goal_position().abs_diff(r.present_position());
+                    if error <= in_position {
+                        status |= IN_POSITION;
+                    } else if !active {
+                        // 軌道が終わっても目標に届いていない
+                        status |= FOLLOWING_ERROR;
+                    }
+                }
+                _ => (),
+            }
+            let moving = active || r.present_velocity().unsigned_abs() > r.moving_threshold();
+            w.moving().bits(moving as u8).moving_status().bits(status)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::control_data::OperatingMode;
+    use crate::control_table::BitsW;
+    use crate::moving::MovingDetector;
+    use crate::profile::ProfileGenerator;
+    use crate::{ControlTableData, XC330};
+    use core::time::Duration;
+
+    const MODE: OperatingMode = OperatingMode::PositionControlMode;
+
+    #[test]
+    fn in_position() {
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        let profile = ProfileGenerator::new(Duration::from_millis(1));
+        let detector = MovingDetector::new(5);
+        ctd.modify(|_, w| w.goal_position().bits(1000).present_position().bits(996));
+        detector.update(&ctd, MODE, &profile);
+        assert_eq!(ctd.read().moving(), 0);
+        assert_eq!(ctd.read().moving_status(), 0x01);
+
+        // 止まっているのに目標に届いていない
+        ctd.modify(|_, w| w.present_position().bits(900));
+        detector.update(&ctd, MODE, &profile);
+        assert_eq!(ctd.read().moving_status(), 0x08);
+
+        // 速度モードでは位置を見ない
+        detector.update(&ctd, OperatingMode::VelocityControlMode, &profile);
+        assert_eq!(ctd.read().moving_status(), 0x00);
+    }
+
+    #[test]
+    fn moving_threshold() {
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        let profile = ProfileGenerator::new(Duration::from_millis(1));
+        let detector = MovingDetector::new(5);
+        ctd.modify(|_, w| w.present_velocity().bits(-10));
+        detector.update(&ctd, MODE, &profile);
+        assert_eq!(ctd.read().moving(), 0);
+        ctd.modify(|_, w| w.present_velocity().bits(-11));
+        detector.update(&ctd, MODE, &profile);
+        assert_eq!(ctd.read().moving(), 1);
+    }
+
+    #[test]
+    fn profile() {
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        let mut profile = ProfileGenerator::new(Duration::from_millis(1));
+        let detector = MovingDetector::new(5);
+        ctd.modify(|_, w| {
+            w.torque_enable()
+                .bits(1)
+                .profile_velocity()
+                .bits(100)
+                .profile_accleration()
+                .bits(100)
+                .goal_position()
+                .bits(2000)
+        });
+        profile.update(&ctd, MODE);
+        detector.update(&ctd, MODE, &profile);
+        assert_eq!(ctd.read().moving(), 1);
+        // 台形の軌道を実行中
+        assert_eq!(ctd.read().moving_status(), 0x32);
+
+        // 現在位置が軌道に追従すれば到達したときに止まる
+        while profile.is_active() {
+            profile.update(&ctd, MODE);
+            let position = ctd.read().position_trajectory();
+            ctd.modify(|_, w| w.present_position().bits(position));
+            detector.update(&ctd, MODE, &profile);
+        }
+        assert_eq!(ctd.read().moving(), 0);
+        assert_eq!(ctd.read().moving_status(), 0x31);
+    }
+}
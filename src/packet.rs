@@ -0,0 +1,383 @@
+//! Borrowed views of received packets and a builder of packets to send.
+//!
+//! `parse` checks the header, the length field and the CRC once, so the accessors can index the
+//! packet without further checks. The packet must have its byte stuffing removed as returned by
+//! `PacketParser`; the CRC is still the one calculated over the stuffed packet on the bus.
+use crate::crc::{Crc16, CrcCalculator};
+use crate::packet_handler::{Packet, BROADCAST_ID, MAX_PACKET_LEN};
+use crate::CommunicationResult;
+use crate::Error;
+use crate::Instruction;
+
+use heapless::Vec;
+
+const HEADER: [u8; 4] = [0xFF, 0xFF, 0xFD, 0x00];
+
+/// Packet sent by the master.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InstructionPacket<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> InstructionPacket<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, Error> {
+        check_frame(data)?;
+        if data[Packet::Instruction.to_pos()] == Instruction::Status.into() {
+            return Err(CommunicationResult::RxCorrupt.into());
+        }
+        Ok(Self { data })
+    }
+
+    /// `data` must be a complete instruction packet from `PacketParser`, which has already
+    /// checked the frame and the CRC.
+    pub(crate) fn new_unchecked(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub fn id(&self) -> u8 {
+        self.data[Packet::Id.to_pos()]
+    }
+
+    pub fn instruction(&self) -> Instruction {
+        Instruction::from_value(self.data[Packet::Instruction.to_pos()])
+    }
+
+    /// Parameters between the instruction and the CRC.
+    pub fn params(&self) -> &'a [u8] {
+        &self.data[Packet::Instruction.to_pos() + 1..self.data.len() - 2]
+    }
+
+    /// Whole packet including the header and the CRC.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+/// Packet returned by a device.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StatusPacket<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StatusPacket<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, Error> {
+        check_frame(data)?;
+        if data[Packet::Instruction.to_pos()] != Instruction::Status.into() {
+            return Err(CommunicationResult::RxCorrupt.into());
+        }
+        // instruction + error + crc(2)
+        if data.len() < Packet::Error.to_pos() + 1 + 2 {
+            return Err(Error::InvalidLength);
+        }
+        Ok(Self { data })
+    }
+
+    pub fn id(&self) -> u8 {
+        self.data[Packet::Id.to_pos()]
+    }
+
+    pub fn instruction(&self) -> Instruction {
+        Instruction::Status
+    }
+
+    /// Error field. Bit 7 is the hardware alert and the rest is the `ErrorBit` number.
+    pub fn error(&self) -> u8 {
+        self.data[Packet::Error.to_pos()]
+    }
+
+    pub fn alert(&self) -> bool {
+        self.error() & 0x80 != 0
+    }
+
+    /// Parameters between the error and the CRC.
+    pub fn params(&self) -> &'a [u8] {
+        &self.data[Packet::Error.to_pos() + 1..self.data.len() - 2]
+    }
+
+    /// Whole packet including the header and the CRC.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+/// Assemble an instruction or status packet ready to be sent.
+///
+/// Parameters are byte stuffed while they are added, and `finish` fixes the length field and
+/// appends the CRC. `N` is the maximum length of the packet including the stuffing.
+pub struct PacketBuilder<const N: usize = MAX_PACKET_LEN> {
+    buf: Vec<u8, N>,
+    // byte stuffingの対象になる先頭位置
+    params_start: usize,
+}
+
+impl<const N: usize> PacketBuilder<N> {
+    // header(4) + id + length(2) + instruction + error + crc(2)が入らないサイズはコンパイル時にエラーにする
+    const CHECK_LEN: () = assert!(N >= 11, "packet buffer is too small");
+
+    pub fn new_instruction(id: u8, instruction: Instruction) -> Self {
+        Self::new_packet(id, instruction.into(), None)
+    }
+
+    /// `error` is the error field of the status packet (`ErrorBit` and the alert bit).
+    pub fn new_status(id: u8, error: u8) -> Self {
+        Self::new_packet(id, Instruction::Status.into(), Some(error))
+    }
+
+    fn new_packet(id: u8, instruction: u8, error: Option<u8>) -> Self {
+        let () = Self::CHECK_LEN;
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&HEADER).unwrap();
+        buf.extend_from_slice(&[id, 0, 0, instruction]).unwrap();
+        if let Some(e) = error {
+            buf.push(e).unwrap();
+        }
+        Self {
+            buf,
+            params_start: Packet::Parameter0.to_pos(),
+        }
+    }
+
+    pub fn push(&mut self, data: u8) -> Result<(), Error> {
+        let len = self.buf.len();
+        let stuffing = data == 0xFD
+            && len >= self.params_start + 2
+            && self.buf[len - 2] == 0xFF
+            && self.buf[len - 1] == 0xFF;
+        // crc(2)の分を残しておく
+        let required = if stuffing { 2 } else { 1 } + 2;
+        if len + required > self.buf.capacity() {
+            return Err(Error::BufferFull);
+        }
+        self.buf.push(data).unwrap();
+        if stuffing {
+            // FF FF FD -> FF FF FD FD
+            self.buf.push(0xFD).unwrap();
+        }
+        Ok(())
+    }
+
+    pub fn extend(&mut self, data: &[u8]) -> Result<(), Error> {
+        for d in data {
+            self.push(*d)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Vec<u8, N> {
+        self.finish_with_crc(&mut Crc16::new())
+    }
+
+    /// Same as `finish` but calculate the CRC with `crc` (e.g. a hardware CRC unit).
+    pub fn finish_with_crc<R>(mut self, crc: &mut R) -> Vec<u8, N>
+    where
+        R: CrcCalculator,
+    {
+        // instruction以降 + crc(2)
+        let length = (self.buf.len() - Packet::Instruction.to_pos() + 2) as u16;
+        self.buf[Packet::LengthL.to_pos()] = length.to_le_bytes()[0];
+        self.buf[Packet::LengthH.to_pos()] = length.to_le_bytes()[1];
+        let crc = crc.checksum(&self.buf);
+        self.buf.extend_from_slice(&crc.to_le_bytes()).unwrap();
+        self.buf
+    }
+}
+
+fn check_frame(data: &[u8]) -> Result<(), Error> {
+    // header(4) + id + length(2) + instruction + crc(2)
+    if data.len() < Packet::Instruction.to_pos() + 1 + 2 {
+        return Err(Error::InvalidLength);
+    }
+    if data[..HEADER.len()] != HEADER {
+        return Err(CommunicationResult::RxCorrupt.into());
+    }
+    let id = data[Packet::Id.to_pos()];
+    if id > 0xFC && id != BROADCAST_ID {
+        return Err(CommunicationResult::RxCorrupt.into());
+    }
+    let length = u16::from_le_bytes([
+        data[Packet::LengthL.to_pos()],
+        data[Packet::LengthH.to_pos()],
+    ]) as usize;
+    if length + Packet::LengthH.to_pos() + 1 != data.len() {
+        return Err(Error::InvalidLength);
+    }
+    let crc = u16::from_le_bytes([data[data.len() - 2], data[data.len() - 1]]);
+    if stuffed_crc(data) != crc {
+        return Err(CommunicationResult::RxCRCError.into());
+    }
+    Ok(())
+}
+
+// byte stuffingを戻して送られてきたときのCRCを計算する
+fn stuffed_crc(data: &[u8]) -> u16 {
+    let body = &data[Packet::Instruction.to_pos()..data.len() - 2];
+    // パラメータ中のFF FF FDの後ろにFDが追加される
+    let is_stuffed =
+        |i: usize| i >= 3 && body[i - 2] == 0xFF && body[i - 1] == 0xFF && body[i] == 0xFD;
+    let stuffing = (0..body.len()).filter(|i| is_stuffed(*i)).count();
+    let length = (body.len() + stuffing + 2) as u16;
+
+    let mut crc = Crc16::new();
+    crc.update(&data[..Packet::LengthL.to_pos()]);
+    crc.update(&length.to_le_bytes());
+    for (i, d) in body.iter().enumerate() {
+        crc.update(&[*d]);
+        if is_stuffed(i) {
+            crc.update(&[0xFD]);
+        }
+    }
+    crc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::packet::{InstructionPacket, PacketBuilder, StatusPacket};
+    use crate::parser::{PacketParser, ParseStatus};
+    use crate::CommunicationResult;
+    use crate::Error;
+    use crate::Instruction;
+
+    #[test]
+    fn instruction() {
+        // Read Instruction Packet ID : 1, address 132, length 4
+        let data = [
+            0xFF, 0xFF, 0xFD, 0x00, 0x01, 0x07, 0x00, 0x02, 0x84, 0x00, 0x04, 0x00, 0x1D, 0x15,
+        ];
+        let packet = InstructionPacket::parse(&data).unwrap();
+        assert_eq!(packet.id(), 1);
+        assert_eq!(packet.instruction(), Instruction::Read);
+        assert_eq!(packet.params(), [0x84, 0x00, 0x04, 0x00]);
+        assert_eq!(packet.as_bytes(), data);
+
+        // Status Packetは受け付けない
+        assert_eq!(
+            StatusPacket::parse(&data),
+            Err(Error::Communication(CommunicationResult::RxCorrupt))
+        );
+    }
+
+    #[test]
+    fn status() {
+        let data = [
+            0xFF, 0xFF, 0xFD, 0x00, 0x01, 0x07, 0x00, 0x55, 0x80, 0x01, 0x02, 0x03, 0xE8, 0xC9,
+        ];
+        let packet = StatusPacket::parse(&data).unwrap();
+        assert_eq!(packet.id(), 1);
+        assert_eq!(packet.instruction(), Instruction::Status);
+        assert_eq!(packet.error(), 0x80);
+        assert!(packet.alert());
+        assert_eq!(packet.params(), [0x01, 0x02, 0x03]);
+        assert_eq!(
+            InstructionPacket::parse(&data),
+            Err(Error::Communication(CommunicationResult::RxCorrupt))
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        let data = [0xFF, 0xFF, 0xFD, 0x00, 0x01, 0x03, 0x00, 0x01, 0x19, 0x4E];
+        assert!(InstructionPacket::parse(&data).is_ok());
+        assert_eq!(
+            InstructionPacket::parse(&data[..9]),
+            Err(Error::InvalidLength)
+        );
+
+        let mut broken = data;
+        broken[9] = 0x4F;
+        assert_eq!(
+            InstructionPacket::parse(&broken),
+            Err(Error::Communication(CommunicationResult::RxCRCError))
+        );
+
+        let mut broken = data;
+        broken[2] = 0xFE;
+        assert_eq!(
+            InstructionPacket::parse(&broken),
+            Err(Error::Communication(CommunicationResult::RxCorrupt))
+        );
+
+        let mut broken = data;
+        broken[5] = 0x04;
+        assert_eq!(InstructionPacket::parse(&broken), Err(Error::InvalidLength));
+    }
+
+    #[test]
+    fn stuffing() {
+        // 送信時はFF FF FD FD 01だったWrite Instruction
+        let data = [
+            0xFF, 0xFF, 0xFD, 0x00, 0x01, 0x09, 0x00, 0x03, 0xE0, 0x00, 0xFF, 0xFF, 0xFD, 0x01,
+            0x67, 0x7A,
+        ];
+        let packet = InstructionPacket::parse(&data).unwrap();
+        assert_eq!(packet.instruction(), Instruction::Write);
+        assert_eq!(packet.params(), [0xE0, 0x00, 0xFF, 0xFF, 0xFD, 0x01]);
+    }
+
+    #[test]
+    fn build_status() {
+        // ID1(XM430-W210) : For Model Number 1030(0x0406), Version of Firmware 38(0x26)
+        let mut builder: PacketBuilder = PacketBuilder::new_status(1, 0);
+        builder.extend(&0x0406u16.to_le_bytes()).unwrap();
+        builder.push(0x26).unwrap();
+        assert_eq!(
+            builder.finish(),
+            [0xFF, 0xFF, 0xFD, 0x00, 0x01, 0x07, 0x00, 0x55, 0x00, 0x06, 0x04, 0x26, 0x65, 0x5D]
+        );
+    }
+
+    #[test]
+    fn build_instruction() {
+        let mut builder: PacketBuilder = PacketBuilder::new_instruction(1, Instruction::Read);
+        builder.extend(&[0x84, 0x00, 0x04, 0x00]).unwrap();
+        assert_eq!(
+            builder.finish(),
+            [0xFF, 0xFF, 0xFD, 0x00, 0x01, 0x07, 0x00, 0x02, 0x84, 0x00, 0x04, 0x00, 0x1D, 0x15]
+        );
+    }
+
+    #[test]
+    fn build_stuffing() {
+        let mut builder: PacketBuilder = PacketBuilder::new_instruction(1, Instruction::Write);
+        builder
+            .extend(&[0xE0, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFD, 0x01])
+            .unwrap();
+        let packet = builder.finish();
+        assert_eq!(
+            packet[..packet.len() - 2],
+            [
+                0xFF, 0xFF, 0xFD, 0x00, 0x01, 0x0C, 0x00, 0x03, 0xE0, 0x00, 0xFF, 0xFF, 0xFF, 0xFF,
+                0xFD, 0xFD, 0x01
+            ]
+        );
+
+        // 受信側で元に戻ること
+        let mut parser = PacketParser::new();
+        let mut status = ParseStatus::Waiting;
+        for d in packet {
+            status = parser.push(d);
+        }
+        assert_eq!(status, ParseStatus::Complete);
+        let received = InstructionPacket::parse(parser.packet()).unwrap();
+        assert_eq!(
+            received.params(),
+            [0xE0, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFD, 0x01]
+        );
+    }
+
+    #[test]
+    fn build_overflow() {
+        let mut builder: PacketBuilder = PacketBuilder::new_status(1, 0);
+        // header(7) + instruction + error + crc(2)
+        assert_eq!(builder.extend(&[0; 256 - 11]), Ok(()));
+        assert_eq!(builder.push(0), Err(Error::BufferFull));
+        assert_eq!(builder.finish().len(), 256);
+
+        // 小さいバッファではstuffingの分も含めて溢れないこと
+        let mut builder = PacketBuilder::<16>::new_status(1, 0);
+        assert_eq!(
+            builder.extend(&[0x00, 0x00, 0xFF, 0xFF, 0xFD]),
+            Err(Error::BufferFull)
+        );
+        assert_eq!(builder.finish().len(), 15);
+    }
+}
@@ -0,0 +1,325 @@
+//! Incremental parser of Dynamixel Protocol 2.0 packets.
+//!
+//! `PacketParser` consumes one byte at a time and keeps no more state than the packet being
+//! received, so it can be fed directly from the UART RX interrupt.
+use crate::crc::{Crc16, CrcCalculator};
+use crate::packet_handler::{DynamixelPacket, Packet, BROADCAST_ID, MAX_PACKET_LEN};
+
+use heapless::Vec;
+
+const HEADER: [u8; 4] = [0xFF, 0xFF, 0xFD, 0x00];
+// HEADER0 HEADER1 HEADER2 RESERVED ID LENGTH_L LENGTH_H
+const HEADER_LEN: usize = 7;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ParserState {
+    Header0,
+    Header1,
+    Header2,
+    Reserved,
+    Id,
+    LengthL,
+    LengthH,
+    Body,
+    Complete,
+}
+
+/// Result of feeding one byte to `PacketParser`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParseStatus {
+    /// The packet is not complete yet.
+    Waiting,
+    /// Bytes were discarded to search for the next header.
+    Resync,
+    /// The packet does not fit in the buffer and was discarded.
+    Overflow,
+    /// The packet was received but the CRC did not match.
+    CrcError,
+    /// A valid packet is available from `packet()` until the next byte is pushed.
+    Complete,
+}
+
+/// `N` is the maximum length of the packet on the bus. Longer packets are discarded.
+pub struct PacketParser<R = Crc16, const N: usize = MAX_PACKET_LEN>
+where
+    R: CrcCalculator,
+{
+    state: ParserState,
+    buf: Vec<u8, N>,
+    packet_length: usize,
+    crc: R,
+    discarding: bool,
+}
+
+impl PacketParser<Crc16> {
+    pub fn new() -> Self {
+        Self::with_crc(Crc16::new())
+    }
+}
+
+impl<R, const N: usize> PacketParser<R, N>
+where
+    R: CrcCalculator,
+{
+    /// Use `crc` (e.g. a hardware CRC unit) to verify received packets.
+    pub fn with_crc(crc: R) -> Self {
+        Self {
+            state: ParserState::Header0,
+            buf: Vec::new(),
+            packet_length: 0,
+            crc,
+            discarding: false,
+        }
+    }
+
+    /// Drop the packet being received.
+    pub fn reset(&mut self) {
+        self.state = ParserState::Header0;
+        self.buf.clear();
+        self.discarding = false;
+    }
+
+    /// True while a header has been found and the rest of the packet is awaited.
+    pub fn is_receiving(&self) -> bool {
+        self.state != ParserState::Header0 && self.state != ParserState::Complete
+    }
+
+    /// Complete packet with byte stuffing removed, or the bytes received so far.
+    pub fn packet(&self) -> &[u8] {
+        &self.buf
+    }
+
+    pub fn push(&mut self, byte: u8) -> ParseStatus {
+        match self.state {
+            ParserState::Complete => {
+                self.buf.clear();
+                self.state = ParserState::Header0;
+                self.push(byte)
+            }
+            ParserState::Header0 => {
+                if byte == HEADER[0] {
+                    self.buf.clear();
+                    self.buf.push(byte).unwrap();
+                    self.state = ParserState::Header1;
+                    ParseStatus::Waiting
+                } else {
+                    self.discard()
+                }
+            }
+            ParserState::Header1 | ParserState::Header2 | ParserState::Reserved => {
+                let pos = self.buf.len();
+                if byte == HEADER[pos] {
+                    self.buf.push(byte).unwrap();
+                    self.state = match self.state {
+                        ParserState::Header1 => ParserState::Header2,
+                        ParserState::Header2 => ParserState::Reserved,
+                        _ => {
+                            self.crc.reset();
+                            self.crc.update(&HEADER);
+                            ParserState::Id
+                        }
+                    };
+                    ParseStatus::Waiting
+                } else {
+                    self.resync(byte)
+                }
+            }
+            ParserState::Id => {
+                if byte > 0xFC && byte != BROADCAST_ID {
+                    return self.resync(byte);
+                }
+                self.accept(byte);
+                self.state = ParserState::LengthL;
+                ParseStatus::Waiting
+            }
+            ParserState::LengthL => {
+                self.accept(byte);
+                self.state = ParserState::LengthH;
+                ParseStatus::Waiting
+            }
+            ParserState::LengthH => {
+                let packet_length = u16::from_le_bytes([self.buf[Packet::LengthL.to_pos()], byte]);
+                // instruction + crc(2)より短いものはない
+                if packet_length < 3 {
+                    return self.resync(byte);
+                }
+                if packet_length as usize + HEADER_LEN > N {
+                    // バッファに入り切らないので捨てる
+                    self.state = ParserState::Header0;
+                    self.buf.clear();
+                    self.discarding = true;
+                    return ParseStatus::Overflow;
+                }
+                self.accept(byte);
+                self.packet_length = packet_length as usize;
+                self.discarding = false;
+                self.state = ParserState::Body;
+                ParseStatus::Waiting
+            }
+            ParserState::Body => {
+                self.buf.push(byte).unwrap();
+                let end = HEADER_LEN + self.packet_length;
+                if self.buf.len() <= end - 2 {
+                    self.crc.update(&[byte]);
+                }
+                if self.buf.len() < end {
+                    return ParseStatus::Waiting;
+                }
+                let crc = u16::from_le_bytes([self.buf[end - 2], self.buf[end - 1]]);
+                if crc != self.crc.finish() {
+                    self.state = ParserState::Header0;
+                    self.buf.clear();
+                    return ParseStatus::CrcError;
+                }
+                self.buf.remove_stuffing();
+                self.state = ParserState::Complete;
+                ParseStatus::Complete
+            }
+        }
+    }
+
+    fn accept(&mut self, byte: u8) {
+        self.buf.push(byte).unwrap();
+        self.crc.update(&[byte]);
+    }
+
+    // 連続したゴミは1回のresyncとして数える
+    fn discard(&mut self) -> ParseStatus {
+        if self.discarding {
+            ParseStatus::Waiting
+        } else {
+            self.discarding = true;
+            ParseStatus::Resync
+        }
+    }
+
+    // ヘッダの途中で不一致になったら先頭1byteを捨てて残りからヘッダを探し直す
+    fn resync(&mut self, byte: u8) -> ParseStatus {
+        let mut rest = Vec::<u8, HEADER_LEN>::new();
+        rest.extend_from_slice(&self.buf[1..]).unwrap();
+        rest.push(byte).unwrap();
+        let status = self.discard();
+        self.state = ParserState::Header0;
+        self.buf.clear();
+        for b in rest {
+            self.push(b);
+        }
+        status
+    }
+}
+
+impl<const N: usize> Default for PacketParser<Crc16, N> {
+    fn default() -> Self {
+        Self::with_crc(Crc16::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crc::Crc16;
+    use crate::parser::{PacketParser, ParseStatus};
+
+    fn push_all(parser: &mut PacketParser, data: &[u8]) -> ParseStatus {
+        let mut status = ParseStatus::Waiting;
+        for (i, d) in data.iter().enumerate() {
+            status = parser.push(*d);
+            // 最後の1byteより前に完了しないこと
+            assert!(status != ParseStatus::Complete || i == data.len() - 1);
+        }
+        status
+    }
+
+    #[test]
+    fn ping() {
+        let mut parser = PacketParser::new();
+        let instruction = [0xFF, 0xFF, 0xFD, 0x00, 0x01, 0x03, 0x00, 0x01, 0x19, 0x4E];
+        assert_eq!(push_all(&mut parser, &instruction), ParseStatus::Complete);
+        assert_eq!(parser.packet(), instruction);
+        assert!(!parser.is_receiving());
+    }
+
+    #[test]
+    fn partial() {
+        let mut parser = PacketParser::new();
+        let instruction = [0xFF, 0xFF, 0xFD, 0x00, 0x01, 0x03, 0x00, 0x01, 0x19, 0x4E];
+        assert_eq!(
+            push_all(&mut parser, &instruction[..6]),
+            ParseStatus::Waiting
+        );
+        assert!(parser.is_receiving());
+        assert_eq!(
+            push_all(&mut parser, &instruction[6..]),
+            ParseStatus::Complete
+        );
+
+        // 続けて次のパケットも受け取れる
+        assert_eq!(push_all(&mut parser, &instruction), ParseStatus::Complete);
+        assert_eq!(parser.packet(), instruction);
+    }
+
+    #[test]
+    fn resync() {
+        let mut parser = PacketParser::new();
+        assert_eq!(parser.push(0x00), ParseStatus::Resync);
+        assert_eq!(parser.push(0x12), ParseStatus::Waiting);
+        // 途中で途切れたヘッダの後ろに正しいパケット
+        let instruction = [
+            0xFF, 0xFF, 0xFF, 0xFD, 0x00, 0x01, 0x03, 0x00, 0x01, 0x19, 0x4E,
+        ];
+        assert_eq!(push_all(&mut parser, &instruction), ParseStatus::Complete);
+        assert_eq!(parser.packet(), &instruction[1..]);
+
+        // 不正なIDの位置からヘッダが始まっている
+        let instruction = [
+            0xFF, 0xFF, 0xFD, 0x00, 0xFF, 0xFF, 0xFD, 0x00, 0x01, 0x03, 0x00, 0x01, 0x19, 0x4E,
+        ];
+        let mut status = ParseStatus::Waiting;
+        let mut resync = 0;
+        for d in instruction {
+            status = parser.push(d);
+            if status == ParseStatus::Resync {
+                resync += 1;
+            }
+        }
+        assert_eq!(status, ParseStatus::Complete);
+        assert_eq!(resync, 1);
+        assert_eq!(parser.packet(), &instruction[4..]);
+    }
+
+    #[test]
+    fn crc_error() {
+        let mut parser = PacketParser::new();
+        let instruction = [0xFF, 0xFF, 0xFD, 0x00, 0x01, 0x03, 0x00, 0x01, 0x19, 0x4F];
+        assert_eq!(push_all(&mut parser, &instruction), ParseStatus::CrcError);
+        assert!(!parser.is_receiving());
+    }
+
+    #[test]
+    fn overflow() {
+        let mut parser = PacketParser::new();
+        let instruction = [0xFF, 0xFF, 0xFD, 0x00, 0x01, 0xFA, 0x00];
+        assert_eq!(push_all(&mut parser, &instruction), ParseStatus::Overflow);
+        assert!(!parser.is_receiving());
+    }
+
+    #[test]
+    fn stuffing() {
+        let mut parser = PacketParser::new();
+        // Write Instruction: address 0x00E0 data FF FF FD 01
+        let mut instruction = [
+            0xFF, 0xFF, 0xFD, 0x00, 0x01, 0x0A, 0x00, 0x03, 0xE0, 0x00, 0xFF, 0xFF, 0xFD, 0xFD,
+            0x01, 0x00, 0x00,
+        ];
+        let crc = Crc16::checksum(&instruction[..15]).to_le_bytes();
+        instruction[15] = crc[0];
+        instruction[16] = crc[1];
+        assert_eq!(push_all(&mut parser, &instruction), ParseStatus::Complete);
+        assert_eq!(
+            parser.packet(),
+            [
+                0xFF, 0xFF, 0xFD, 0x00, 0x01, 0x09, 0x00, 0x03, 0xE0, 0x00, 0xFF, 0xFF, 0xFD, 0x01,
+                crc[0], crc[1]
+            ]
+        );
+    }
+}
@@ -0,0 +1,471 @@
+//! Velocity profile of the goal, following the semantics of the X series.
+//!
+//! `DriveMode` bit 2 selects the profile. In the velocity-based profile `ProfileVelocity` and
+//! `ProfileAccleration` are the maximum velocity and acceleration, and 0 means infinite. In the
+//! time-based profile they are the time to reach the goal and the acceleration time in ms.
+//! The result is written to `PositionTrajectory` and `VelocityTrajectory`, which the controller
+//! follows. In the velocity control mode only `ProfileAccleration` is used. `Moving` and
+//! `MovingStatus` are updated by `MovingDetector` from the state of the generator.
+//!
+//! The profile is generated tick by tick, so a new goal written during a profile continues from
+//! the current trajectory without a jump of velocity.
+use crate::control_data::OperatingMode;
+use crate::control_table::{BitsW, ControlTableAccess, R};
+use crate::layout::ControlTableLayout;
+use crate::utils::round;
+
+use core::time::Duration;
+
+const TIME_BASED_PROFILE: u8 = 0x04;
+
+/// Shape of the velocity profile, as in bit 5-4 of `MovingStatus`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProfileType {
+    Step,
+    Rectangular,
+    Triangular,
+    Trapezoidal,
+}
+
+impl ProfileType {
+    pub fn to_value(&self) -> u8 {
+        match self {
+            ProfileType::Step => 0,
+            ProfileType::Rectangular => 1,
+            ProfileType::Triangular => 2,
+            ProfileType::Trapezoidal => 3,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProfilePhase {
+    Idle,
+    Accelerating,
+    Constant,
+    Decelerating,
+}
+
+// coreにはsqrtがないのでニュートン法で求める
+fn sqrt(x: f32) -> f32 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x == f32::INFINITY {
+        return x;
+    }
+    let mut y = f32::from_bits((x.to_bits() >> 1) + 0x1fbd_1df5);
+    for _ in 0..4 {
+        y = 0.5 * (y + x / y);
+    }
+    y
+}
+
+// [tick/s] / Profile Velocityの単位
+fn velocity_unit<L: ControlTableLayout>() -> f32 {
+    L::VELOCITY_UNIT * L::POSITION_RESOLUTION as f32 / 60.0
+}
+
+// [tick/s^2] / Profile Accelerationの単位
+fn acceleration_unit<L: ControlTableLayout>() -> f32 {
+    L::ACCELERATION_UNIT * L::POSITION_RESOLUTION as f32 / 3600.0
+}
+
+pub struct ProfileGenerator {
+    period: Duration,
+    // None: トルクOFFなどで軌道がない
+    goal: Option<i32>,
+    // [tick], [tick/s], [tick/s^2]
+    position: f32,
+    velocity: f32,
+    max_velocity: f32,
+    acceleration: f32,
+    profile_type: ProfileType,
+    phase: ProfilePhase,
+}
+
+impl ProfileGenerator {
+    /// `period` is the interval between calls of `update`.
+    pub fn new(period: Duration) -> Self {
+        Self {
+            period,
+            goal: None,
+            position: 0.0,
+            velocity: 0.0,
+            max_velocity: 0.0,
+            acceleration: 0.0,
+            profile_type: ProfileType::Step,
+            phase: ProfilePhase::Idle,
+        }
+    }
+
+    /// True while the trajectory has not reached the goal.
+    pub fn is_active(&self) -> bool {
+        self.phase != ProfilePhase::Idle
+    }
+
+    pub fn phase(&self) -> ProfilePhase {
+        self.phase
+    }
+
+    /// Shape of the last planned profile.
+    pub fn profile_type(&self) -> ProfileType {
+        self.profile_type
+    }
+
+    /// Advance the trajectory by one tick. Call this before the controller every control tick.
+    pub fn update<D: ControlTableAccess>(&mut self, ctd: &D, mode: OperatingMode) {
+        let r = ctd.read();
+        if r.torque_enable() == 0 {
+            self.stop(&r);
+        } else {
+            match mode {
+                OperatingMode::PositionControlMode
+                | OperatingMode::ExtendedPosionControlMode
+                | OperatingMode::CurrentBasedPositionControlMode => {
+                    let mut goal = r.goal_position();
+                    if mode == OperatingMode::PositionControlMode {
+                        goal = goal
+                            .clamp(r.min_position_limit() as i32, r.max_position_limit() as i32);
+                    }
+                    if self.goal != Some(goal) {
+                        self.plan(&r, goal);
+                    }
+                    self.step_position(goal);
+                }
+                OperatingMode::VelocityControlMode => self.step_velocity(&r),
+                _ => self.stop(&r),
+            }
+        }
+        let velocity = round(self.velocity / velocity_unit::<D::Layout>());
+        let position = round(self.position);
+        ctd.modify(|_, w| {
+            w.position_trajectory()
+                .bits(position)
+                .velocity_trajectory()
+                .bits(velocity)
+        });
+    }
+
+    // 現在位置に留まる
+    fn stop<L: ControlTableLayout>(&mut self, r: &R<L>) {
+        self.goal = None;
+        self.position = r.present_position() as f32;
+        self.velocity = 0.0;
+        self.phase = ProfilePhase::Idle;
+    }
+
+    fn plan<L: ControlTableLayout>(&mut self, r: &R<L>, goal: i32) {
+        if self.goal.is_none() {
+            self.position = r.present_position() as f32;
+            self.velocity = 0.0;
+        }
+        self.goal = Some(goal);
+        let distance = goal as f32 - self.position;
+        let distance = if distance < 0.0 { -distance } else { distance };
+        let profile_velocity = r.profile_velocity() as f32;
+        let profile_acceleration = r.profile_accleration() as f32;
+        if profile_velocity == 0.0 {
+            self.profile_type = ProfileType::Step;
+            self.max_velocity = f32::INFINITY;
+            self.acceleration = f32::INFINITY;
+        } else if r.drive_mode() & TIME_BASED_PROFILE != 0 {
+            // 加速時間は全体の半分まで
+            let total = profile_velocity / 1000.0;
+            let accel_time = profile_acceleration.min(profile_velocity / 2.0) / 1000.0;
+            if accel_time == 0.0 {
+                self.profile_type = ProfileType::Rectangular;
+                self.max_velocity = distance / total;
+                self.acceleration = f32::INFINITY;
+            } else {
+                self.profile_type = if accel_time * 2.0 < total {
+                    ProfileType::Trapezoidal
+                } else {
+                    ProfileType::Triangular
+                };
+                self.max_velocity = distance / (total - accel_time);
+                self.acceleration = self.max_velocity / accel_time;
+            }
+        } else {
+            self.max_velocity = profile_velocity * velocity_unit::<L>();
+            if profile_acceleration == 0.0 {
+                self.profile_type = ProfileType::Rectangular;
+                self.acceleration = f32::INFINITY;
+            } else {
+                self.acceleration = profile_acceleration * acceleration_unit::<L>();
+                // 加速と減速の距離の和が移動量に収まれば最高速度に届く
+                self.profile_type =
+                    if distance * self.acceleration >= self.max_velocity * self.max_velocity {
+                        ProfileType::Trapezoidal
+                    } else {
+                        ProfileType::Triangular
+                    };
+            }
+        }
+    }
+
+    fn step_position(&mut self, goal: i32) {
+        let dt = self.period.as_secs_f32();
+        let remaining = goal as f32 - self.position;
+        let (direction, remaining) = if remaining < 0.0 {
+            (-1.0, -remaining)
+        } else {
+            (1.0, remaining)
+        };
+        let speed = self.velocity * direction;
+        let dv = self.acceleration * dt;
+        // 次のtickで進んだ後の残り距離で止まれる速さv: v^2 = 2a(remaining - (speed + v) / 2 * dt)
+        let braking = if self.acceleration == f32::INFINITY {
+            f32::INFINITY
+        } else {
+            (-dv + sqrt(dv * dv - 4.0 * (dv * speed - 2.0 * self.acceleration * remaining))) / 2.0
+        };
+        let mut next = (speed + dv).min(self.max_velocity).min(braking);
+        // 目標に向かって動いている間は、1 tickで加速度を超えて減速しない
+        if speed > 0.0 {
+            next = next.max(speed - dv).max(0.0);
+        }
+        // 台形積分で進める
+        let step = (speed + next) / 2.0 * dt;
+        if step >= remaining || next <= 0.0 && speed >= 0.0 {
+            self.position = goal as f32;
+            self.velocity = 0.0;
+            self.phase = ProfilePhase::Idle;
+            return;
+        }
+        let magnitude = |v: f32| if v < 0.0 { -v } else { v };
+        self.phase = if magnitude(next) > magnitude(speed) {
+            ProfilePhase::Accelerating
+        } else if magnitude(next) < magnitude(speed) {
+            ProfilePhase::Decelerating
+        } else {
+            ProfilePhase::Constant
+        };
+        self.position += direction * step;
+        self.velocity = direction * next;
+    }
+
+    fn step_velocity<L: ControlTableLayout>(&mut self, r: &R<L>) {
+        self.goal = None;
+        self.position = r.present_position() as f32;
+        let limit = r.velocity_limit() as i32;
+        let goal = r.goal_velocity().clamp(-limit, limit) as f32 * velocity_unit::<L>();
+        let acceleration = match r.profile_accleration() {
+            0 => f32::INFINITY,
+            a => a as f32 * acceleration_unit::<L>(),
+        };
+        self.profile_type = if acceleration == f32::INFINITY {
+            ProfileType::Rectangular
+        } else {
+            ProfileType::Trapezoidal
+        };
+        let dv = acceleration * self.period.as_secs_f32();
+        if goal > self.velocity + dv {
+            self.velocity += dv;
+            self.phase = ProfilePhase::Accelerating;
+        } else if goal < self.velocity - dv {
+            self.velocity -= dv;
+            self.phase = ProfilePhase::Decelerating;
+        } else {
+            self.velocity = goal;
+            self.phase = ProfilePhase::Idle;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::control_data::OperatingMode;
+    use crate::control_table::BitsW;
+    use crate::profile::{sqrt, ProfileGenerator, ProfilePhase, ProfileType};
+    use crate::{ControlTableData, XC330};
+    use core::time::Duration;
+
+    const MODE: OperatingMode = OperatingMode::ExtendedPosionControlMode;
+
+    // 到達するまでのtick数
+    fn run(profile: &mut ProfileGenerator, ctd: &ControlTableData) -> usize {
+        let mut ticks = 0;
+        let mut last = ctd.read().position_trajectory();
+        loop {
+            profile.update(ctd, MODE);
+            ticks += 1;
+            let position = ctd.read().position_trajectory();
+            // 一方向に単調に進む
+            assert!(position >= last);
+            last = position;
+            if !profile.is_active() {
+                return ticks;
+            }
+            assert!(ticks < 100_000);
+        }
+    }
+
+    #[test]
+    fn sqrt_newton() {
+        for x in [0.01f32, 2.0, 100.0, 1.0e8] {
+            let y = sqrt(x);
+            assert!((y * y - x).abs() / x < 1e-5);
+        }
+        assert_eq!(sqrt(0.0), 0.0);
+    }
+
+    #[test]
+    fn step() {
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        ctd.modify(|_, w| w.torque_enable().bits(1));
+        let mut profile = ProfileGenerator::new(Duration::from_millis(1));
+        ctd.modify(|_, w| w.goal_position().bits(1000));
+        profile.update(&ctd, MODE);
+        assert_eq!(profile.profile_type(), ProfileType::Step);
+        assert!(!profile.is_active());
+        assert_eq!(ctd.read().position_trajectory(), 1000);
+    }
+
+    #[test]
+    fn velocity_based() {
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        ctd.modify(|_, w| w.torque_enable().bits(1));
+        let mut profile = ProfileGenerator::new(Duration::from_millis(1));
+        // 100 * 0.229rpm = 1563 tick/s
+        ctd.modify(|_, w| {
+            w.profile_velocity()
+                .bits(100)
+                .profile_accleration()
+                .bits(100)
+                .goal_position()
+                .bits(4000)
+        });
+        profile.update(&ctd, MODE);
+        assert_eq!(profile.profile_type(), ProfileType::Trapezoidal);
+        assert_eq!(profile.phase(), ProfilePhase::Accelerating);
+
+        let mut phases = [false; 3];
+        while profile.is_active() {
+            match profile.phase() {
+                ProfilePhase::Accelerating => phases[0] = true,
+                ProfilePhase::Constant => {
+                    phases[1] = true;
+                    assert_eq!(ctd.read().velocity_trajectory(), 100);
+                }
+                ProfilePhase::Decelerating => phases[2] = true,
+                ProfilePhase::Idle => (),
+            }
+            profile.update(&ctd, MODE);
+        }
+        assert_eq!(phases, [true; 3]);
+        assert_eq!(ctd.read().position_trajectory(), 4000);
+        assert_eq!(ctd.read().velocity_trajectory(), 0);
+
+        // 短い距離では最高速度に届かない
+        ctd.modify(|_, w| w.goal_position().bits(4100));
+        profile.update(&ctd, MODE);
+        assert_eq!(profile.profile_type(), ProfileType::Triangular);
+        run(&mut profile, &ctd);
+        assert_eq!(ctd.read().position_trajectory(), 4100);
+
+        // 加速度0は矩形
+        ctd.modify(|_, w| w.profile_accleration().bits(0).goal_position().bits(5000));
+        profile.update(&ctd, MODE);
+        assert_eq!(profile.profile_type(), ProfileType::Rectangular);
+        assert_eq!(ctd.read().velocity_trajectory(), 100);
+    }
+
+    #[test]
+    fn change_goal() {
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        ctd.modify(|_, w| w.torque_enable().bits(1));
+        let mut profile = ProfileGenerator::new(Duration::from_millis(1));
+        ctd.modify(|_, w| {
+            w.profile_velocity()
+                .bits(100)
+                .profile_accleration()
+                .bits(100)
+                .goal_position()
+                .bits(4000)
+        });
+        for _ in 0..500 {
+            profile.update(&ctd, MODE);
+        }
+        // 動作中に逆向きの目標を与えても速度は連続に変化する
+        ctd.modify(|_, w| w.goal_position().bits(0));
+        let mut last = ctd.read().velocity_trajectory();
+        assert_eq!(last, 100);
+        while profile.is_active() {
+            profile.update(&ctd, MODE);
+            let velocity = ctd.read().velocity_trajectory();
+            assert!((velocity - last).abs() <= 2);
+            last = velocity;
+        }
+        assert_eq!(ctd.read().position_trajectory(), 0);
+    }
+
+    #[test]
+    fn time_based() {
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        ctd.modify(|_, w| w.torque_enable().bits(1));
+        let mut profile = ProfileGenerator::new(Duration::from_millis(1));
+        ctd.modify(|_, w| {
+            w.drive_mode()
+                .bits(0x04)
+                .profile_velocity()
+                .bits(500)
+                .profile_accleration()
+                .bits(100)
+                .goal_position()
+                .bits(2000)
+        });
+        let ticks = run(&mut profile, &ctd);
+        assert_eq!(profile.profile_type(), ProfileType::Trapezoidal);
+        assert!((495..=505).contains(&ticks), "{}", ticks);
+
+        // 加速時間は半分で制限される
+        ctd.modify(|_, w| w.profile_accleration().bits(400).goal_position().bits(3000));
+        let ticks = run(&mut profile, &ctd);
+        assert_eq!(profile.profile_type(), ProfileType::Triangular);
+        assert!((495..=505).contains(&ticks), "{}", ticks);
+    }
+
+    #[test]
+    fn torque_off() {
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        let mut profile = ProfileGenerator::new(Duration::from_millis(1));
+        ctd.modify(|_, w| w.present_position().bits(300).goal_position().bits(1000));
+        profile.update(&ctd, MODE);
+        assert!(!profile.is_active());
+        assert_eq!(ctd.read().position_trajectory(), 300);
+
+        // トルクONで現在位置から始める
+        ctd.modify(|_, w| {
+            w.torque_enable()
+                .bits(1)
+                .profile_velocity()
+                .bits(100)
+                .goal_position()
+                .bits(200)
+        });
+        profile.update(&ctd, MODE);
+        assert!(profile.is_active());
+        let position = ctd.read().position_trajectory();
+        assert!(position < 300 && position > 200);
+    }
+
+    #[test]
+    fn velocity_mode() {
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        ctd.modify(|_, w| w.torque_enable().bits(1));
+        let mut profile = ProfileGenerator::new(Duration::from_millis(1));
+        ctd.modify(|_, w| w.profile_accleration().bits(100).goal_velocity().bits(100));
+        let mut ticks = 0;
+        loop {
+            profile.update(&ctd, OperatingMode::VelocityControlMode);
+            if !profile.is_active() {
+                break;
+            }
+            ticks += 1;
+        }
+        // 100 * 0.229rpm / (100 * 214.577rpm/min) = 64ms
+        assert!((60..=68).contains(&ticks), "{}", ticks);
+        assert_eq!(ctd.read().velocity_trajectory(), 100);
+    }
+}
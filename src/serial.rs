@@ -0,0 +1,312 @@
+//! Linux serial port transport for host side tools.
+//!
+//! `SerialPort` opens any tty (USB serial adapter or pseudo-terminal) in raw, non-blocking mode.
+//! The bit rate is set with `termios2`, so rates without a `Bxxxx` constant such as 4.5Mbps also work.
+use crate::BufferInterface;
+use crate::Clock;
+
+use core::time::Duration;
+use std::ffi::CString;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::time::Instant;
+
+// linux/serial.h
+const ASYNC_LOW_LATENCY: libc::c_int = 1 << 13;
+
+#[repr(C)]
+struct SerialStruct {
+    type_: libc::c_int,
+    line: libc::c_int,
+    port: libc::c_uint,
+    irq: libc::c_int,
+    flags: libc::c_int,
+    xmit_fifo_size: libc::c_int,
+    custom_divisor: libc::c_int,
+    baud_base: libc::c_int,
+    close_delay: libc::c_ushort,
+    io_type: libc::c_char,
+    reserved_char: [libc::c_char; 1],
+    hub6: libc::c_int,
+    closing_wait: libc::c_ushort,
+    closing_wait2: libc::c_ushort,
+    iomem_base: *mut libc::c_uchar,
+    iomem_reg_shift: libc::c_ushort,
+    port_high: libc::c_uint,
+    iomap_base: libc::c_ulong,
+}
+
+pub struct SerialPort {
+    fd: RawFd,
+    baudrate: u32,
+}
+
+impl SerialPort {
+    pub fn open(path: &str, baudrate: u32) -> io::Result<Self> {
+        let c_path =
+            CString::new(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let fd = unsafe {
+            libc::open(
+                c_path.as_ptr(),
+                libc::O_RDWR | libc::O_NOCTTY | libc::O_NONBLOCK | libc::O_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let port = Self { fd, baudrate };
+        port.configure(baudrate)?;
+        // 疑似端末などではlow latencyを設定できないので失敗しても無視する
+        let _ = port.set_low_latency();
+        Ok(port)
+    }
+
+    pub fn baudrate(&self) -> u32 {
+        self.baudrate
+    }
+
+    fn configure(&self, baudrate: u32) -> io::Result<()> {
+        let mut tio: libc::termios2 = unsafe { core::mem::zeroed() };
+        if unsafe { libc::ioctl(self.fd, libc::TCGETS2, &mut tio) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // raw mode, 8N1
+        tio.c_iflag &= !(libc::IGNBRK
+            | libc::BRKINT
+            | libc::PARMRK
+            | libc::ISTRIP
+            | libc::INLCR
+            | libc::IGNCR
+            | libc::ICRNL
+            | libc::IXON
+            | libc::IXOFF);
+        tio.c_oflag &= !libc::OPOST;
+        tio.c_lflag &= !(libc::ECHO | libc::ECHONL | libc::ICANON | libc::ISIG | libc::IEXTEN);
+        tio.c_cflag &= !(libc::CSIZE | libc::PARENB | libc::CSTOPB | libc::CRTSCTS | libc::CBAUD);
+        tio.c_cflag |= libc::CS8 | libc::CLOCAL | libc::CREAD | libc::BOTHER;
+        tio.c_ispeed = baudrate;
+        tio.c_ospeed = baudrate;
+        tio.c_cc[libc::VMIN] = 0;
+        tio.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::ioctl(self.fd, libc::TCSETS2, &tio) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn set_low_latency(&self) -> io::Result<()> {
+        let mut serial: SerialStruct = unsafe { core::mem::zeroed() };
+        if unsafe { libc::ioctl(self.fd, libc::TIOCGSERIAL, &mut serial) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        serial.flags |= ASYNC_LOW_LATENCY;
+        if unsafe { libc::ioctl(self.fd, libc::TIOCSSERIAL, &serial) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SerialPort {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+impl BufferInterface for SerialPort {
+    fn write_byte(&mut self, data: u8) {
+        self.write_bytes(&[data]);
+    }
+
+    fn write_bytes(&mut self, data: &[u8]) {
+        let mut written = 0;
+        while written < data.len() {
+            let n = unsafe {
+                libc::write(
+                    self.fd,
+                    data[written..].as_ptr() as *const libc::c_void,
+                    data.len() - written,
+                )
+            };
+            if n < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::Interrupted
+                {
+                    continue;
+                }
+                return;
+            }
+            written += n as usize;
+        }
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        let mut buf = [0u8; 1];
+        match self.read_bytes(&mut buf) {
+            Some(1) => Some(buf[0]),
+            _ => None,
+        }
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if buf.is_empty() {
+            return Some(0);
+        }
+        let n = unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if n < 0 {
+            None
+        } else {
+            Some(n as usize)
+        }
+    }
+
+    fn clear_read_buf(&mut self) {
+        unsafe {
+            libc::tcflush(self.fd, libc::TCIFLUSH);
+        }
+    }
+
+    fn set_baudrate(&mut self, baudrate: u32) {
+        // 返信済みの送信データを出し切ってから切り替える
+        unsafe {
+            libc::tcdrain(self.fd);
+        }
+        if self.configure(baudrate).is_ok() {
+            self.baudrate = baudrate;
+        }
+    }
+}
+
+/// `Clock` backed by `std::time::Instant`.
+pub struct StdClock {
+    start: Instant,
+}
+
+impl StdClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for StdClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for StdClock {
+    fn get_current_time(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::control_table::BitsW;
+    use crate::serial::{SerialPort, StdClock};
+    use crate::BufferInterface;
+    use crate::Clock;
+    use crate::ControlTableData;
+    use crate::DynamixelProtocolHandler;
+    use std::ffi::CStr;
+    use std::os::unix::io::RawFd;
+    use std::string::String;
+    use std::time::{Duration, Instant};
+    use std::vec::Vec;
+
+    // 疑似端末のマスター側を開き、スレーブ側のパスを返す
+    fn open_pty() -> (RawFd, String) {
+        unsafe {
+            let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_NONBLOCK);
+            assert!(master >= 0);
+            assert_eq!(libc::grantpt(master), 0);
+            assert_eq!(libc::unlockpt(master), 0);
+            let mut name = [0 as libc::c_char; 128];
+            assert_eq!(libc::ptsname_r(master, name.as_mut_ptr(), name.len()), 0);
+            let path = CStr::from_ptr(name.as_ptr()).to_str().unwrap().into();
+            (master, path)
+        }
+    }
+
+    fn read_master(master: RawFd, len: usize, timeout: Duration) -> Vec<u8> {
+        let start = Instant::now();
+        let mut received = Vec::new();
+        while received.len() < len && start.elapsed() < timeout {
+            let mut buf = [0u8; 64];
+            let n = unsafe { libc::read(master, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+            if n > 0 {
+                received.extend_from_slice(&buf[..n as usize]);
+            }
+        }
+        received
+    }
+
+    #[test]
+    fn pty_loopback() {
+        let (master, path) = open_pty();
+        let mut port = SerialPort::open(&path, 4_500_000).unwrap();
+        assert_eq!(port.baudrate(), 4_500_000);
+
+        // 受信データがなければ0
+        let mut buf = [0u8; 4];
+        assert_eq!(port.read_bytes(&mut buf).unwrap_or(0), 0);
+
+        port.write_bytes(&[0x01, 0x02, 0xFF]);
+        assert_eq!(
+            read_master(master, 3, Duration::from_secs(1)),
+            [0x01, 0x02, 0xFF]
+        );
+
+        port.set_baudrate(57_600);
+        assert_eq!(port.baudrate(), 57_600);
+        unsafe { libc::close(master) };
+    }
+
+    #[test]
+    fn ping_over_pty() {
+        let (master, path) = open_pty();
+        let port = SerialPort::open(&path, 1_000_000).unwrap();
+        let control_table_data = ControlTableData::new();
+        control_table_data.modify(|_, w| w.model_number().bits(0x0406));
+        control_table_data.modify(|_, w| w.firmware_version().bits(0x26));
+        control_table_data.modify(|_, w| w.id().bits(1));
+        let mut dxl =
+            DynamixelProtocolHandler::new(port, StdClock::new(), 1_000_000, control_table_data);
+
+        // Ping Instruction Packet ID : 1
+        let instruction: [u8; 10] = [0xFF, 0xFF, 0xFD, 0x00, 0x01, 0x03, 0x00, 0x01, 0x19, 0x4E];
+        let n = unsafe {
+            libc::write(
+                master,
+                instruction.as_ptr() as *const libc::c_void,
+                instruction.len(),
+            )
+        };
+        assert_eq!(n, instruction.len() as isize);
+
+        let start = Instant::now();
+        let mut response = Vec::new();
+        while response.len() < 14 && start.elapsed() < Duration::from_secs(1) {
+            assert_eq!(dxl.parse_data(), Ok(()));
+            response.extend(read_master(master, 14, Duration::from_millis(1)));
+        }
+        assert_eq!(
+            response,
+            [0xFF, 0xFF, 0xFD, 0x00, 0x01, 0x07, 0x00, 0x55, 0x00, 0x06, 0x04, 0x26, 0x65, 0x5D]
+        );
+        unsafe { libc::close(master) };
+    }
+
+    #[test]
+    fn std_clock() {
+        let clock = StdClock::new();
+        let t0 = clock.get_current_time();
+        std::thread::sleep(Duration::from_millis(2));
+        assert!(clock.get_current_time() >= t0 + Duration::from_millis(2));
+    }
+}
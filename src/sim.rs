@@ -0,0 +1,390 @@
+//! Virtual half-duplex bus for testing several nodes on one line without hardware.
+//!
+//! Every node gets a `BusPort` (`BufferInterface`) and a `BusClock` (`Clock`) that share one
+//! simulated time. A byte written by a node occupies the line for 10 bit times (start, 8 data, stop)
+//! and is delivered to all the other nodes when its stop bit has been sent.
+//! Bytes from different nodes that overlap on the line are counted as a collision and
+//! are delivered as the wired-AND of the colliding bytes.
+//! A port can also receive its own bytes back, like a transceiver that loops TX to RX.
+use crate::BufferInterface;
+use crate::Clock;
+
+use core::cell::RefCell;
+use core::time::Duration;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::vec::Vec;
+
+const BITS_PER_BYTE: u64 = 10; // start bit + 8 data bits + stop bit
+
+struct Slot {
+    from: usize,
+    baudrate: u32,
+    start: Duration,
+    end: Duration,
+    data: u8,
+}
+
+struct Node {
+    rx: VecDeque<u8>,
+    baudrate: u32,
+    tx_end: Duration,
+    echo: bool,
+}
+
+struct BusState {
+    now: Duration,
+    slots: Vec<Slot>,
+    nodes: Vec<Node>,
+    collisions: usize,
+}
+
+impl BusState {
+    fn byte_time(baudrate: u32) -> Duration {
+        Duration::from_nanos(BITS_PER_BYTE * 1_000_000_000 / baudrate as u64)
+    }
+
+    fn transmit(&mut self, from: usize, data: &[u8]) {
+        let baudrate = self.nodes[from].baudrate;
+        let byte_time = Self::byte_time(baudrate);
+        // 自ノードの送信が終わるまでは次のバイトを送らない(UARTの送信FIFO)
+        let mut start = core::cmp::max(self.now, self.nodes[from].tx_end);
+        for d in data {
+            let end = start + byte_time;
+            let mut value = *d;
+            for slot in self.slots.iter_mut() {
+                if slot.from != from && slot.start < end && start < slot.end {
+                    self.collisions += 1;
+                    value &= slot.data;
+                    slot.data &= *d;
+                }
+            }
+            self.slots.push(Slot {
+                from,
+                baudrate,
+                start,
+                end,
+                data: value,
+            });
+            start = end;
+        }
+        self.nodes[from].tx_end = start;
+    }
+
+    fn deliver(&mut self) {
+        let now = self.now;
+        let mut done: Vec<Slot> = Vec::new();
+        let mut i = 0;
+        while i < self.slots.len() {
+            if self.slots[i].end <= now {
+                done.push(self.slots.remove(i));
+            } else {
+                i += 1;
+            }
+        }
+        done.sort_by_key(|s| s.end);
+        for slot in done {
+            for (n, node) in self.nodes.iter_mut().enumerate() {
+                // ボーレートが異なるノードはフレーミングエラーで受け取れない
+                if (n != slot.from || node.echo) && node.baudrate == slot.baudrate {
+                    node.rx.push_back(slot.data);
+                }
+            }
+        }
+    }
+}
+
+/// Simulated bus shared by all connected nodes.
+#[derive(Clone)]
+pub struct VirtualBus {
+    state: Rc<RefCell<BusState>>,
+}
+
+impl VirtualBus {
+    pub fn new() -> Self {
+        Self {
+            state: Rc::new(RefCell::new(BusState {
+                now: Duration::new(0, 0),
+                slots: Vec::new(),
+                nodes: Vec::new(),
+                collisions: 0,
+            })),
+        }
+    }
+
+    /// Connect a new node to the bus.
+    pub fn connect(&self, baudrate: u32) -> BusPort {
+        let mut state = self.state.borrow_mut();
+        let now = state.now;
+        state.nodes.push(Node {
+            rx: VecDeque::new(),
+            baudrate,
+            tx_end: now,
+            echo: false,
+        });
+        BusPort {
+            state: self.state.clone(),
+            node: state.nodes.len() - 1,
+        }
+    }
+
+    pub fn clock(&self) -> BusClock {
+        BusClock {
+            state: self.state.clone(),
+        }
+    }
+
+    pub fn now(&self) -> Duration {
+        self.state.borrow().now
+    }
+
+    /// Advance the simulated time and deliver the bytes that have been sent by then.
+    pub fn advance(&self, dt: Duration) {
+        let mut state = self.state.borrow_mut();
+        state.now += dt;
+        state.deliver();
+    }
+
+    /// No byte is on the line.
+    pub fn is_idle(&self) -> bool {
+        self.state.borrow().slots.is_empty()
+    }
+
+    /// Number of overlapping byte pairs seen on the line.
+    pub fn collisions(&self) -> usize {
+        self.state.borrow().collisions
+    }
+}
+
+impl Default for VirtualBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// `BufferInterface` of one node on a `VirtualBus`.
+pub struct BusPort {
+    state: Rc<RefCell<BusState>>,
+    node: usize,
+}
+
+impl BusPort {
+    pub fn baudrate(&self) -> u32 {
+        self.state.borrow().nodes[self.node].baudrate
+    }
+
+    /// Receive own transmitted bytes back.
+    pub fn set_echo(&mut self, enable: bool) {
+        self.state.borrow_mut().nodes[self.node].echo = enable;
+    }
+}
+
+impl BufferInterface for BusPort {
+    fn write_byte(&mut self, data: u8) {
+        self.state.borrow_mut().transmit(self.node, &[data]);
+    }
+
+    fn write_bytes(&mut self, data: &[u8]) {
+        self.state.borrow_mut().transmit(self.node, data);
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        self.state.borrow_mut().nodes[self.node].rx.pop_front()
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Option<usize> {
+        let mut state = self.state.borrow_mut();
+        let rx = &mut state.nodes[self.node].rx;
+        let m = core::cmp::min(rx.len(), buf.len());
+        for b in buf.iter_mut().take(m) {
+            *b = rx.pop_front().unwrap();
+        }
+        Some(m)
+    }
+
+    fn clear_read_buf(&mut self) {
+        self.state.borrow_mut().nodes[self.node].rx.clear();
+    }
+
+    fn set_baudrate(&mut self, baudrate: u32) {
+        self.state.borrow_mut().nodes[self.node].baudrate = baudrate;
+    }
+}
+
+/// `Clock` reading the simulated time of a `VirtualBus`.
+pub struct BusClock {
+    state: Rc<RefCell<BusState>>,
+}
+
+impl Clock for BusClock {
+    fn get_current_time(&self) -> Duration {
+        self.state.borrow().now
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::control_table::BitsW;
+    use crate::sim::VirtualBus;
+    use crate::BufferInterface;
+    use crate::CommunicationResult;
+    use crate::ControlTableData;
+    use crate::DynamixelProtocolHandler;
+    use crate::Error;
+    use core::time::Duration;
+    use std::vec::Vec;
+
+    fn read_all(port: &mut impl BufferInterface) -> Vec<u8> {
+        let mut received = Vec::new();
+        while let Some(d) = port.read_byte() {
+            received.push(d);
+        }
+        received
+    }
+
+    #[test]
+    fn deliver_to_other_nodes() {
+        let bus = VirtualBus::new();
+        let mut a = bus.connect(1_000_000);
+        let mut b = bus.connect(1_000_000);
+        let mut c = bus.connect(1_000_000);
+
+        a.write_bytes(&[0x01, 0x02]);
+        // 1byte = 10us
+        bus.advance(Duration::from_micros(10));
+        assert_eq!(read_all(&mut b), [0x01]);
+        bus.advance(Duration::from_micros(10));
+        assert_eq!(read_all(&mut b), [0x02]);
+        assert_eq!(read_all(&mut c), [0x01, 0x02]);
+        // 自分の送信は受け取らない
+        assert!(read_all(&mut a).is_empty());
+        assert!(bus.is_idle());
+        assert_eq!(bus.collisions(), 0);
+    }
+
+    #[test]
+    fn collision() {
+        let bus = VirtualBus::new();
+        let mut a = bus.connect(1_000_000);
+        let mut b = bus.connect(1_000_000);
+        let mut c = bus.connect(1_000_000);
+
+        a.write_bytes(&[0xF0]);
+        bus.advance(Duration::from_micros(5));
+        b.write_bytes(&[0x0F]);
+        bus.advance(Duration::from_micros(20));
+        assert_eq!(bus.collisions(), 1);
+        assert_eq!(read_all(&mut c), [0x00, 0x00]);
+    }
+
+    #[test]
+    fn echo() {
+        let bus = VirtualBus::new();
+        let mut a = bus.connect(1_000_000);
+        let mut b = bus.connect(1_000_000);
+        a.set_echo(true);
+
+        a.write_bytes(&[0x01, 0x02]);
+        bus.advance(Duration::from_micros(20));
+        assert_eq!(read_all(&mut a), [0x01, 0x02]);
+        assert_eq!(read_all(&mut b), [0x01, 0x02]);
+    }
+
+    #[test]
+    fn duplicated_id_collision() {
+        let bus = VirtualBus::new();
+        let mut master = bus.connect(1_000_000);
+        let mut port1 = bus.connect(1_000_000);
+        let mut port2 = bus.connect(1_000_000);
+        port1.set_echo(true);
+        port2.set_echo(true);
+        let control_table_data1 = ControlTableData::new();
+        control_table_data1.modify(|_, w| w.model_number().bits(0x0406));
+        control_table_data1.modify(|_, w| w.id().bits(1));
+        // 同じIDで異なるモデルのデバイスが繋がっている
+        let control_table_data2 = ControlTableData::new();
+        control_table_data2.modify(|_, w| w.model_number().bits(0x0424));
+        control_table_data2.modify(|_, w| w.id().bits(1));
+        let mut dxl1 =
+            DynamixelProtocolHandler::new(port1, bus.clock(), 1_000_000, control_table_data1);
+        let mut dxl2 =
+            DynamixelProtocolHandler::new(port2, bus.clock(), 1_000_000, control_table_data2);
+        dxl1.set_echo_check(true);
+        dxl2.set_echo_check(true);
+
+        // Ping Instruction Packet ID : 1
+        master.write_bytes(&[0xFF, 0xFF, 0xFD, 0x00, 0x01, 0x03, 0x00, 0x01, 0x19, 0x4E]);
+
+        let mut result1 = Ok(());
+        let mut result2 = Ok(());
+        for _ in 0..100 {
+            bus.advance(Duration::from_micros(10));
+            result1 = result1.and(dxl1.parse_data());
+            result2 = result2.and(dxl2.parse_data());
+        }
+        read_all(&mut master);
+
+        assert!(bus.collisions() > 0);
+        assert_eq!(
+            result1,
+            Err(Error::Communication(CommunicationResult::TxCollision))
+        );
+        assert_eq!(
+            result2,
+            Err(Error::Communication(CommunicationResult::TxCollision))
+        );
+        assert_eq!(*dxl1.tx_result(), CommunicationResult::TxCollision);
+        assert_eq!(*dxl2.tx_result(), CommunicationResult::TxCollision);
+        assert_eq!(dxl1.stats().tx_collisions, 1);
+        assert_eq!(dxl2.stats().tx_collisions, 1);
+    }
+
+    #[test]
+    fn sync_read() {
+        let bus = VirtualBus::new();
+        let mut master = bus.connect(115200);
+        let control_table_data1 = ControlTableData::new();
+        control_table_data1.modify(|_, w| w.id().bits(1));
+        control_table_data1.modify(|_, w| w.present_position().bits(166));
+        let control_table_data2 = ControlTableData::new();
+        control_table_data2.modify(|_, w| w.id().bits(2));
+        control_table_data2.modify(|_, w| w.present_position().bits(2079));
+        let mut dxl1 = DynamixelProtocolHandler::new(
+            bus.connect(115200),
+            bus.clock(),
+            115200,
+            control_table_data1,
+        );
+        let mut dxl2 = DynamixelProtocolHandler::new(
+            bus.connect(115200),
+            bus.clock(),
+            115200,
+            control_table_data2,
+        );
+
+        // ID1(XM430-W210) : Present Position(132, 0x0084, 4[byte]) = 166(0x000000A6)
+        // ID2(XM430-W210) : Present Position(132, 0x0084, 4[byte]) = 2,079(0x0000081F)
+        master.write_bytes(&[
+            0xFF, 0xFF, 0xFD, 0x00, 0xFE, 0x09, 0x00, 0x82, 0x84, 0x00, 0x04, 0x00, 0x01, 0x02,
+            0xCE, 0xFA,
+        ]);
+
+        let mut received = Vec::new();
+        for _ in 0..1000 {
+            bus.advance(Duration::from_micros(10));
+            assert_eq!(dxl1.parse_data(), Ok(()));
+            assert_eq!(dxl2.parse_data(), Ok(()));
+            received.extend(read_all(&mut master));
+        }
+
+        assert_eq!(bus.collisions(), 0);
+        assert_eq!(
+            received,
+            [
+                0xFF, 0xFF, 0xFD, 0x00, 0x01, 0x08, 0x00, 0x55, 0x00, 0xA6, 0x00, 0x00, 0x00, 0x8C,
+                0xC0, 0xFF, 0xFF, 0xFD, 0x00, 0x02, 0x08, 0x00, 0x55, 0x00, 0x1F, 0x08, 0x00, 0x00,
+                0xBA, 0xBE
+            ]
+        );
+    }
+}
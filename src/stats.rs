@@ -0,0 +1,100 @@
+use crate::CommunicationResult;
+use crate::Error;
+
+/// Unused area between BackupReady(147) and IndirectAddress1(168) of the XC330 control table.
+pub const DEFAULT_STATS_ADDRESS: u16 = 148;
+/// Number of bytes used when the statistics are mapped into the control table.
+pub const STATS_MAP_SIZE: usize = 19;
+
+/// Counters of the bus health seen by `DynamixelProtocolHandler`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CommunicationStats {
+    /// Valid packets received, including packets for other devices.
+    pub packets_received: u32,
+    /// Valid instruction packets addressed to this device or broadcast.
+    pub addressed_to_us: u32,
+    pub crc_errors: u32,
+    /// Times the parser dropped bytes to search for the next header.
+    pub header_resyncs: u32,
+    pub timeouts: u32,
+    pub replies_sent: u32,
+    /// Packets dropped because they do not fit in the packet buffer.
+    pub dropped_buffer_full: u32,
+    pub tx_collisions: u32,
+    pub tx_fails: u32,
+    pub last_error: Option<Error>,
+}
+
+impl CommunicationStats {
+    /// Serialize into the control table image.
+    ///
+    /// Counters are stored as saturated little endian `u16` in the order of the fields,
+    /// followed by the code of the last error.
+    pub fn to_bytes(&self) -> [u8; STATS_MAP_SIZE] {
+        let mut bytes = [0; STATS_MAP_SIZE];
+        let counters = [
+            self.packets_received,
+            self.addressed_to_us,
+            self.crc_errors,
+            self.header_resyncs,
+            self.timeouts,
+            self.replies_sent,
+            self.dropped_buffer_full,
+            self.tx_collisions,
+            self.tx_fails,
+        ];
+        for (i, c) in counters.iter().enumerate() {
+            let v = core::cmp::min(*c, u16::MAX as u32) as u16;
+            bytes[i * 2..i * 2 + 2].copy_from_slice(&v.to_le_bytes());
+        }
+        bytes[STATS_MAP_SIZE - 1] = error_code(self.last_error);
+        bytes
+    }
+}
+
+/// Code of the error used in the control table image. 0 means no error.
+pub fn error_code(error: Option<Error>) -> u8 {
+    match error {
+        None => 0,
+        Some(Error::Communication(c)) => match c {
+            CommunicationResult::Success => 0,
+            CommunicationResult::PortBusy => 1,
+            CommunicationResult::TxFail => 2,
+            CommunicationResult::RxFail => 3,
+            CommunicationResult::TxError => 4,
+            CommunicationResult::TxCollision => 5,
+            CommunicationResult::RxWaiting => 6,
+            CommunicationResult::RxTimeout => 7,
+            CommunicationResult::RxCorrupt => 8,
+            CommunicationResult::RxCRCError => 9,
+            CommunicationResult::NotAvailable => 10,
+            CommunicationResult::SomethingWentWrong => 11,
+        },
+        Some(Error::BufferFull) => 0x80,
+        Some(Error::InvalidLength) => 0x81,
+        Some(Error::Unsupported) => 0x82,
+        Some(Error::Storage) => 0x83,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stats::CommunicationStats;
+    use crate::CommunicationResult;
+    use crate::Error;
+
+    #[test]
+    fn to_bytes() {
+        let stats = CommunicationStats {
+            packets_received: 0x1234,
+            crc_errors: 2,
+            tx_fails: 0x10000,
+            last_error: Some(Error::Communication(CommunicationResult::RxCRCError)),
+            ..Default::default()
+        };
+        assert_eq!(
+            stats.to_bytes(),
+            [0x34, 0x12, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xFF, 0xFF, 9]
+        );
+    }
+}
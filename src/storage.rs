@@ -0,0 +1,386 @@
+//! Persistence of the EEPROM area of the control table.
+//!
+//! The EEPROM area (address 0-63) is saved to a `NvStorage` as an image with a header and CRC,
+//! and restored at boot. `EepromPersistence::update` is called periodically from the main loop,
+//! and writes the image after the area has stopped changing for the debounce time, so that a
+//! sequence of writes from the master costs only one erase.
+use crate::control_table::{ControlTable, ControlTableAccess};
+use crate::crc::Crc16;
+use crate::layout::{Access, ControlTableLayout};
+use crate::Error;
+
+use core::time::Duration;
+
+/// Size of the EEPROM area of the control table.
+pub const EEPROM_SIZE: usize = 64;
+/// Incremented when the format of the image changes. Images of other versions are ignored.
+pub const IMAGE_VERSION: u8 = 1;
+
+const MAGIC: [u8; 2] = [0x44, 0x58];
+// MAGIC(2) VERSION(1) SIZE(1) MODEL_NUMBER(2)
+const HEADER_LEN: usize = 6;
+/// Bytes used in the storage.
+pub const IMAGE_LEN: usize = HEADER_LEN + EEPROM_SIZE + 2;
+
+/// Flash memory or EEPROM used to keep the control table over a reset.
+pub trait NvStorage {
+    /// Size of the erase unit in bytes.
+    fn page_size(&self) -> usize;
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Error>;
+    /// Write to an erased area.
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Error>;
+    /// Erase the page starting at `offset`.
+    fn erase_page(&mut self, offset: usize) -> Result<(), Error>;
+}
+
+/// `NvStorage` on RAM which behaves like a flash memory, for tests and simulation.
+pub struct RamStorage<const N: usize, const PAGE: usize = 256> {
+    data: [u8; N],
+    erase_count: u32,
+}
+
+impl<const N: usize, const PAGE: usize> RamStorage<N, PAGE> {
+    pub fn new() -> Self {
+        Self {
+            data: [0xFF; N],
+            erase_count: 0,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn erase_count(&self) -> u32 {
+        self.erase_count
+    }
+}
+
+impl<const N: usize, const PAGE: usize> Default for RamStorage<N, PAGE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const PAGE: usize> NvStorage for RamStorage<N, PAGE> {
+    fn page_size(&self) -> usize {
+        PAGE
+    }
+
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+        if offset + buf.len() > N {
+            return Err(Error::Storage);
+        }
+        buf.copy_from_slice(&self.data[offset..offset + buf.len()]);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Error> {
+        if offset + data.len() > N {
+            return Err(Error::Storage);
+        }
+        // フラッシュと同様に消去しないと1には戻せない
+        for (d, w) in self.data[offset..].iter_mut().zip(data) {
+            *d &= *w;
+        }
+        Ok(())
+    }
+
+    fn erase_page(&mut self, offset: usize) -> Result<(), Error> {
+        if !offset.is_multiple_of(PAGE) || offset + PAGE > N {
+            return Err(Error::Storage);
+        }
+        self.data[offset..offset + PAGE].fill(0xFF);
+        self.erase_count += 1;
+        Ok(())
+    }
+}
+
+/// Keeps the EEPROM area of the control table in `S`.
+pub struct EepromPersistence<S: NvStorage> {
+    storage: S,
+    offset: usize,
+    debounce: Duration,
+    // 最後に保存した内容と、変化を検出した内容と時刻
+    saved: [u8; EEPROM_SIZE],
+    pending: Option<([u8; EEPROM_SIZE], Duration)>,
+}
+
+impl<S: NvStorage> EepromPersistence<S> {
+    /// The image is placed at `offset`, which should be aligned to the page size.
+    pub fn new(storage: S, offset: usize) -> Self {
+        Self {
+            storage,
+            offset,
+            debounce: Duration::from_millis(500),
+            saved: [0; EEPROM_SIZE],
+            pending: None,
+        }
+    }
+
+    /// Time to wait after the last change before writing. 500 ms by default.
+    pub fn set_debounce(&mut self, debounce: Duration) {
+        self.debounce = debounce;
+    }
+
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    /// Restore the writable EEPROM registers from the storage.
+    ///
+    /// Returns `Ok(false)` and leaves the table as is if no valid image of the same model is found,
+    /// e.g. at the first boot. Read only registers such as Model Number are not overwritten.
+    pub fn restore<D: ControlTableAccess>(&mut self, ctd: &D) -> Result<bool, Error> {
+        let mut image = [0; IMAGE_LEN];
+        self.storage.read(self.offset, &mut image)?;
+        let crc = u16::from_le_bytes([image[IMAGE_LEN - 2], image[IMAGE_LEN - 1]]);
+        if image[0..2] != MAGIC
+            || image[2] != IMAGE_VERSION
+            || image[3] as usize != EEPROM_SIZE
+            || u16::from_le_bytes([image[4], image[5]]) != D::Layout::MODEL_NUMBER
+            || crc != Crc16::checksum(&image[..IMAGE_LEN - 2])
+        {
+            self.saved = eeprom(ctd);
+            return Ok(false);
+        }
+        let data = &image[HEADER_LEN..HEADER_LEN + EEPROM_SIZE];
+        ctd.modify(|_, w| {
+            for ct in ControlTable::ALL {
+                if ct.access() != Access::ReadWrite {
+                    continue;
+                }
+                if let Some(spec) = D::Layout::register(*ct) {
+                    let start = spec.address as usize;
+                    let end = start + spec.size as usize;
+                    if end <= EEPROM_SIZE {
+                        w.bytes(start, &data[start..end]);
+                    }
+                }
+            }
+            w
+        });
+        self.saved = eeprom(ctd);
+        self.pending = None;
+        Ok(true)
+    }
+
+    /// Detect changes of the EEPROM area and write them after the debounce time.
+    ///
+    /// Returns `Ok(true)` when the image was written.
+    pub fn update<D: ControlTableAccess>(&mut self, ctd: &D, now: Duration) -> Result<bool, Error> {
+        let current = eeprom(ctd);
+        match self.pending {
+            Some((data, changed_at)) if data == current => {
+                if now < changed_at + self.debounce {
+                    return Ok(false);
+                }
+            }
+            _ => {
+                if current == self.saved {
+                    self.pending = None;
+                } else {
+                    // 変化が続いている間は待つ
+                    self.pending = Some((current, now));
+                }
+                return Ok(false);
+            }
+        }
+        self.save::<D::Layout>(&current)?;
+        Ok(true)
+    }
+
+    /// Write the EEPROM area immediately.
+    pub fn flush<D: ControlTableAccess>(&mut self, ctd: &D) -> Result<(), Error> {
+        self.save::<D::Layout>(&eeprom(ctd))
+    }
+
+    fn save<L: ControlTableLayout>(&mut self, data: &[u8; EEPROM_SIZE]) -> Result<(), Error> {
+        let mut image = [0; IMAGE_LEN];
+        image[0..2].copy_from_slice(&MAGIC);
+        image[2] = IMAGE_VERSION;
+        image[3] = EEPROM_SIZE as u8;
+        image[4..6].copy_from_slice(&L::MODEL_NUMBER.to_le_bytes());
+        image[HEADER_LEN..HEADER_LEN + EEPROM_SIZE].copy_from_slice(data);
+        let crc = Crc16::checksum(&image[..IMAGE_LEN - 2]);
+        image[IMAGE_LEN - 2..].copy_from_slice(&crc.to_le_bytes());
+
+        let page_size = self.storage.page_size();
+        let mut page = self.offset;
+        while page < self.offset + IMAGE_LEN {
+            self.storage.erase_page(page)?;
+            page += page_size;
+        }
+        self.storage.write(self.offset, &image)?;
+        self.saved = *data;
+        self.pending = None;
+        Ok(())
+    }
+}
+
+fn eeprom<D: ControlTableAccess>(ctd: &D) -> [u8; EEPROM_SIZE] {
+    let mut data = [0; EEPROM_SIZE];
+    data.copy_from_slice(&ctd.read().bits().as_ref()[..EEPROM_SIZE]);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::control_table::BitsW;
+    use crate::storage::{EepromPersistence, NvStorage, RamStorage, IMAGE_LEN};
+    use crate::{ControlTableData, Error, SharedControlTableData, XC330, XM430};
+    use core::time::Duration;
+
+    #[test]
+    fn ram_storage() {
+        let mut storage = RamStorage::<512, 256>::new();
+        storage.write(0, &[0x0F, 0xF0]).unwrap();
+        storage.write(0, &[0xF0, 0xF0]).unwrap();
+        let mut buf = [0; 2];
+        storage.read(0, &mut buf).unwrap();
+        assert_eq!(buf, [0x00, 0xF0]);
+        storage.erase_page(0).unwrap();
+        storage.read(0, &mut buf).unwrap();
+        assert_eq!(buf, [0xFF, 0xFF]);
+        assert_eq!(storage.erase_page(100), Err(Error::Storage));
+        assert_eq!(storage.read(511, &mut buf), Err(Error::Storage));
+    }
+
+    #[test]
+    fn save_and_restore() {
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        let mut persistence = EepromPersistence::new(RamStorage::<512>::new(), 256);
+        // 最初は保存されていない
+        assert_eq!(persistence.restore(&ctd), Ok(false));
+        assert_eq!(ctd.read().id(), 1);
+
+        ctd.modify(|_, w| w.id().bits(5));
+        ctd.modify(|_, w| w.homing_offset().bits(-100));
+        // RAM領域は保存されない
+        ctd.modify(|_, w| w.goal_position().bits(1000));
+        persistence.flush(&ctd).unwrap();
+
+        let storage = persistence.storage();
+        assert_eq!(storage.data()[256..258], [0x44, 0x58]);
+        let mut storage = RamStorage::<512>::new();
+        storage
+            .write(256, &persistence.storage().data()[256..256 + IMAGE_LEN])
+            .unwrap();
+
+        // 再起動
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        ctd.modify(|_, w| w.firmware_version().bits(0x26));
+        let mut persistence = EepromPersistence::new(storage, 256);
+        assert_eq!(persistence.restore(&ctd), Ok(true));
+        assert_eq!(ctd.read().id(), 5);
+        assert_eq!(ctd.read().homing_offset(), -100);
+        assert_eq!(ctd.read().goal_position(), 0);
+        // 読み込み専用の値はファームウェアのものが残る
+        assert_eq!(ctd.read().firmware_version(), 0x26);
+
+        // 機種が異なるイメージは使わない
+        let ctd = ControlTableData::with_defaults::<XM430>();
+        assert_eq!(persistence.restore(&ctd), Ok(false));
+        assert_eq!(ctd.read().id(), 1);
+    }
+
+    #[test]
+    fn corrupted() {
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        ctd.modify(|_, w| w.id().bits(5));
+        let mut persistence = EepromPersistence::new(RamStorage::<256>::new(), 0);
+        persistence.flush(&ctd).unwrap();
+        let mut storage = RamStorage::<256>::new();
+        let mut image = [0; IMAGE_LEN];
+        image.copy_from_slice(&persistence.storage().data()[..IMAGE_LEN]);
+        image[10] ^= 0x01;
+        storage.write(0, &image).unwrap();
+
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        let mut persistence = EepromPersistence::new(storage, 0);
+        assert_eq!(persistence.restore(&ctd), Ok(false));
+        assert_eq!(ctd.read().id(), 1);
+    }
+
+    #[test]
+    fn shared_control_table() {
+        static CTD: SharedControlTableData = SharedControlTableData::new();
+        CTD.write(|w| w.defaults());
+        let mut persistence = EepromPersistence::new(RamStorage::<256>::new(), 0);
+        assert_eq!(persistence.restore(&CTD), Ok(false));
+        CTD.modify(|_, w| w.id().bits(5));
+        persistence.flush(&CTD).unwrap();
+
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        let mut persistence = EepromPersistence::new(persistence.storage, 0);
+        assert_eq!(persistence.restore(&ctd), Ok(true));
+        assert_eq!(ctd.read().id(), 5);
+    }
+
+    #[test]
+    fn debounce() {
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        let mut persistence = EepromPersistence::new(RamStorage::<256>::new(), 0);
+        persistence.set_debounce(Duration::from_millis(100));
+        assert_eq!(persistence.restore(&ctd), Ok(false));
+        assert_eq!(
+            persistence.update(&ctd, Duration::from_millis(0)),
+            Ok(false)
+        );
+
+        ctd.modify(|_, w| w.id().bits(2));
+        assert_eq!(
+            persistence.update(&ctd, Duration::from_millis(10)),
+            Ok(false)
+        );
+        // 書き込みが続くと待ち時間が延びる
+        ctd.modify(|_, w| w.return_delay_time().bits(0));
+        assert_eq!(
+            persistence.update(&ctd, Duration::from_millis(50)),
+            Ok(false)
+        );
+        assert_eq!(
+            persistence.update(&ctd, Duration::from_millis(120)),
+            Ok(false)
+        );
+        assert_eq!(
+            persistence.update(&ctd, Duration::from_millis(150)),
+            Ok(true)
+        );
+        assert_eq!(persistence.storage().erase_count(), 1);
+
+        // 変化が無ければ書き込まない
+        assert_eq!(
+            persistence.update(&ctd, Duration::from_millis(300)),
+            Ok(false)
+        );
+        // RAM領域の変化は無視する
+        ctd.modify(|_, w| w.goal_position().bits(10));
+        assert_eq!(
+            persistence.update(&ctd, Duration::from_millis(400)),
+            Ok(false)
+        );
+        assert_eq!(
+            persistence.update(&ctd, Duration::from_millis(600)),
+            Ok(false)
+        );
+        assert_eq!(persistence.storage().erase_count(), 1);
+
+        // 元に戻した場合も書き込まない
+        ctd.modify(|_, w| w.id().bits(3));
+        assert_eq!(
+            persistence.update(&ctd, Duration::from_millis(700)),
+            Ok(false)
+        );
+        ctd.modify(|_, w| w.id().bits(2));
+        assert_eq!(
+            persistence.update(&ctd, Duration::from_millis(710)),
+            Ok(false)
+        );
+        assert_eq!(
+            persistence.update(&ctd, Duration::from_millis(900)),
+            Ok(false)
+        );
+        assert_eq!(persistence.storage().erase_count(), 1);
+    }
+}
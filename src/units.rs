@@ -0,0 +1,224 @@
+//! Conversion of the register values to SI units.
+//!
+//! The position registers are in the frame of the output shaft: `HomingOffset` and the reverse
+//! mode of `DriveMode` are applied once, when a raw encoder count is converted with
+//! `raw_to_position` or `raw_to_rad` (as `Controller::update` does). The register accessors such
+//! as `present_position_rad` are therefore only a scale.
+//! The unit of each register is taken from the `ControlTableLayout` of the model.
+use crate::control_table::{BitsW, R, W};
+use crate::layout::ControlTableLayout;
+use crate::utils::round;
+
+use core::f32::consts::PI;
+
+// PWM Limitの最大値が100%
+const PWM_MAX: f32 = 885.0;
+const VOLTAGE_UNIT: f32 = 0.1;
+
+fn rad_per_tick<L: ControlTableLayout>() -> f32 {
+    2.0 * PI / L::POSITION_RESOLUTION as f32
+}
+
+fn rad_s_per_unit<L: ControlTableLayout>() -> f32 {
+    L::VELOCITY_UNIT * 2.0 * PI / 60.0
+}
+
+impl<L: ControlTableLayout> R<L> {
+    /// True if the reverse mode bit of `DriveMode` is set.
+    pub fn is_reverse(&self) -> bool {
+        self.drive_mode() & 0x01 != 0
+    }
+
+    fn direction(&self) -> i32 {
+        if self.is_reverse() {
+            -1
+        } else {
+            1
+        }
+    }
+
+    /// Position register value from a raw encoder count, with `HomingOffset` and the reverse mode
+    /// applied.
+    pub fn raw_to_position(&self, raw: i32) -> i32 {
+        self.direction() * raw + self.homing_offset()
+    }
+    /// Raw encoder count from a position register value.
+    pub fn position_to_raw(&self, position: i32) -> i32 {
+        self.direction() * (position - self.homing_offset())
+    }
+    /// Angle of the output shaft [rad] from a raw encoder count.
+    pub fn raw_to_rad(&self, raw: i32) -> f32 {
+        self.raw_to_position(raw) as f32 * rad_per_tick::<L>()
+    }
+    /// Raw encoder count from an angle of the output shaft [rad].
+    pub fn rad_to_raw(&self, rad: f32) -> i32 {
+        self.position_to_raw(round(rad / rad_per_tick::<L>()))
+    }
+
+    pub fn present_position_rad(&self) -> f32 {
+        self.present_position() as f32 * rad_per_tick::<L>()
+    }
+    pub fn goal_position_rad(&self) -> f32 {
+        self.goal_position() as f32 * rad_per_tick::<L>()
+    }
+    /// [rad/s]
+    pub fn present_velocity_rad_s(&self) -> f32 {
+        self.present_velocity() as f32 * rad_s_per_unit::<L>()
+    }
+    /// [rad/s]
+    pub fn goal_velocity_rad_s(&self) -> f32 {
+        self.goal_velocity() as f32 * rad_s_per_unit::<L>()
+    }
+    pub fn present_current_ma(&self) -> f32 {
+        self.present_current() as f32 * L::CURRENT_UNIT
+    }
+    pub fn goal_current_ma(&self) -> f32 {
+        self.goal_current() as f32 * L::CURRENT_UNIT
+    }
+    pub fn present_pwm_percent(&self) -> f32 {
+        self.present_pwm() as f32 * 100.0 / PWM_MAX
+    }
+    pub fn goal_pwm_percent(&self) -> f32 {
+        self.goal_pwm() as f32 * 100.0 / PWM_MAX
+    }
+    pub fn present_input_voltage_v(&self) -> f32 {
+        self.present_input_voltage() as f32 * VOLTAGE_UNIT
+    }
+    /// [°C]
+    pub fn present_temperature_c(&self) -> f32 {
+        self.present_temperature() as f32
+    }
+}
+
+impl<L: ControlTableLayout> W<L> {
+    pub fn present_position_rad(&mut self, rad: f32) -> &mut Self {
+        self.present_position()
+            .bits(round(rad / rad_per_tick::<L>()))
+    }
+    pub fn goal_position_rad(&mut self, rad: f32) -> &mut Self {
+        self.goal_position().bits(round(rad / rad_per_tick::<L>()))
+    }
+    /// [rad/s]
+    pub fn present_velocity_rad_s(&mut self, rad_s: f32) -> &mut Self {
+        self.present_velocity()
+            .bits(round(rad_s / rad_s_per_unit::<L>()))
+    }
+    /// [rad/s]
+    pub fn goal_velocity_rad_s(&mut self, rad_s: f32) -> &mut Self {
+        self.goal_velocity()
+            .bits(round(rad_s / rad_s_per_unit::<L>()))
+    }
+    pub fn present_current_ma(&mut self, ma: f32) -> &mut Self {
+        self.present_current()
+            .bits(round(ma / L::CURRENT_UNIT) as i16)
+    }
+    pub fn goal_current_ma(&mut self, ma: f32) -> &mut Self {
+        self.goal_current().bits(round(ma / L::CURRENT_UNIT) as i16)
+    }
+    pub fn present_pwm_percent(&mut self, percent: f32) -> &mut Self {
+        self.present_pwm()
+            .bits(round(percent * PWM_MAX / 100.0) as i16)
+    }
+    pub fn goal_pwm_percent(&mut self, percent: f32) -> &mut Self {
+        self.goal_pwm()
+            .bits(round(percent * PWM_MAX / 100.0) as i16)
+    }
+    pub fn present_input_voltage_v(&mut self, v: f32) -> &mut Self {
+        self.present_input_voltage()
+            .bits(round(v / VOLTAGE_UNIT) as u16)
+    }
+    /// [°C]
+    pub fn present_temperature_c(&mut self, c: f32) -> &mut Self {
+        self.present_temperature().bits(round(c) as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::control_table::BitsW;
+    use crate::{ControlTableData, XC330, XM430};
+    use core::f32::consts::PI;
+
+    fn assert_near(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-3, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn position() {
+        let ctd = ControlTableData::new();
+        ctd.modify(|_, w| w.present_position().bits(1024));
+        assert_near(ctd.read().present_position_rad(), PI / 2.0);
+        ctd.modify(|_, w| w.goal_position_rad(-PI));
+        assert_eq!(ctd.read().goal_position(), -2048);
+
+        // Homing Offsetと逆回転モードは制御側で適用済み
+        ctd.modify(|_, w| w.homing_offset().bits(1024).drive_mode().bits(0x01));
+        assert_near(ctd.read().present_position_rad(), PI / 2.0);
+        ctd.modify(|_, w| w.present_position_rad(PI));
+        assert_eq!(ctd.read().present_position(), 2048);
+    }
+
+    #[test]
+    fn raw() {
+        // 逆回転モードとHomingOffsetはエンコーダの値を変換する時に一度だけ適用する
+        let ctd = ControlTableData::new();
+        ctd.modify(|_, w| w.drive_mode().bits(0x01).homing_offset().bits(1024));
+        let r = ctd.read();
+        assert_eq!(r.raw_to_position(256), 768);
+        assert_near(r.raw_to_rad(256), PI * 3.0 / 8.0);
+        assert_eq!(r.position_to_raw(768), 256);
+        assert_eq!(r.rad_to_raw(PI), -1024);
+
+        // 制御側と同じ変換でレジスタに書き込み、単位変換で読み戻す
+        ctd.modify(|r, w| w.present_position().bits(r.raw_to_position(256)));
+        assert_near(ctd.read().present_position_rad(), PI * 3.0 / 8.0);
+        ctd.modify(|_, w| w.goal_position_rad(PI));
+        assert_eq!(ctd.read().goal_position(), 2048);
+        let r = ctd.read();
+        assert_eq!(r.position_to_raw(r.goal_position()), -1024);
+        assert_near(r.raw_to_rad(-1024), r.goal_position_rad());
+    }
+
+    #[test]
+    fn velocity() {
+        let ctd = ControlTableData::new();
+        // 0.229 rpm * 100 = 22.9 rpm
+        ctd.modify(|_, w| w.present_velocity().bits(100));
+        assert_near(ctd.read().present_velocity_rad_s(), 22.9 * 2.0 * PI / 60.0);
+        ctd.modify(|_, w| w.goal_velocity_rad_s(-22.9 * 2.0 * PI / 60.0));
+        assert_eq!(ctd.read().goal_velocity(), -100);
+        ctd.modify(|_, w| w.drive_mode().bits(0x01));
+        assert_near(ctd.read().present_velocity_rad_s(), 22.9 * 2.0 * PI / 60.0);
+    }
+
+    #[test]
+    fn current() {
+        let ctd = ControlTableData::with_defaults::<XC330>();
+        ctd.modify(|_, w| w.goal_current_ma(-120.0));
+        assert_eq!(ctd.read().goal_current(), -120);
+
+        let ctd = ControlTableData::with_defaults::<XM430>();
+        ctd.modify(|_, w| w.present_current().bits(100));
+        assert_near(ctd.read().present_current_ma(), 269.0);
+        ctd.modify(|_, w| w.goal_current_ma(269.0));
+        assert_eq!(ctd.read().goal_current(), 100);
+    }
+
+    #[test]
+    fn others() {
+        let ctd = ControlTableData::new();
+        ctd.modify(|_, w| {
+            w.present_input_voltage_v(5.0)
+                .present_temperature_c(36.6)
+                .present_pwm_percent(-50.0)
+                .goal_pwm_percent(100.0)
+        });
+        assert_eq!(ctd.read().present_input_voltage(), 50);
+        assert_near(ctd.read().present_input_voltage_v(), 5.0);
+        assert_eq!(ctd.read().present_temperature(), 37);
+        assert_near(ctd.read().present_temperature_c(), 37.0);
+        assert_eq!(ctd.read().present_pwm(), -443);
+        assert_eq!(ctd.read().goal_pwm(), 885);
+        assert_near(ctd.read().goal_pwm_percent(), 100.0);
+    }
+}
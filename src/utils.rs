@@ -20,3 +20,12 @@ impl DegRad<f64> for f64 {
         self * 180.0 / core::f64::consts::PI
     }
 }
+
+/// Round half away from zero, as `f32::round` is not available in `no_std`.
+pub(crate) fn round(value: f32) -> i32 {
+    if value >= 0.0 {
+        (value + 0.5) as i32
+    } else {
+        (value - 0.5) as i32
+    }
+}